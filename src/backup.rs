@@ -0,0 +1,198 @@
+use crate::find_and_replace::write_atomic;
+use crate::rename::Rename;
+use anyhow::{bail, Context, Error, Result};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+// snapshot of the original content of every file touched by a single replace
+// operation, stored in a temporary directory keyed by the operation's uuid
+#[derive(Debug, Clone)]
+pub struct Backup {
+    pub dir: PathBuf,
+    pub files: Vec<BackupEntry>,
+    // renamed files and directories in the order they were renamed
+    pub renames: Vec<Rename>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BackupEntry {
+    pub path: PathBuf,
+    pub backup: PathBuf,
+    // hash of the content written by the replace, used to detect later edits
+    pub replaced_hash: u64,
+}
+
+pub fn content_hash(content: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Default for Backup {
+    fn default() -> Self {
+        Backup::new()
+    }
+}
+
+impl Backup {
+    pub fn new() -> Self {
+        Backup::in_dir(&std::env::temp_dir())
+    }
+
+    // a backup saving below `parent` instead of the system temp directory
+    pub fn in_dir(parent: &Path) -> Self {
+        Backup {
+            dir: parent.join(format!("frr-{}", Uuid::new_v4())),
+            files: vec![],
+            renames: vec![],
+        }
+    }
+
+    // an empty backup saving into the same directory, for replacing in several
+    // files at once
+    pub fn fork(&self) -> Self {
+        Backup {
+            dir: self.dir.clone(),
+            files: vec![],
+            renames: vec![],
+        }
+    }
+
+    pub fn merge(&mut self, other: Backup) {
+        self.files.extend(other.files);
+        self.renames.extend(other.renames);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.renames.is_empty()
+    }
+
+    // where a file that was replaced ended up after the renames
+    fn current_path(&self, path: &Path) -> PathBuf {
+        self.renames.iter().fold(path.to_owned(), |path, rename| {
+            match path.strip_prefix(&rename.from) {
+                Ok(rest) if rest.as_os_str().is_empty() => rename.to.clone(),
+                Ok(rest) => rename.to.join(rest),
+                Err(_) => path,
+            }
+        })
+    }
+
+    // must be called before the file at `path` is overwritten with `replaced`
+    pub async fn save(&mut self, path: &Path, original: &str, replaced: &str) -> Result<(), Error> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("Could not create backup directory {:?}", self.dir))?;
+        // unique, as forks of the backup save into the same directory
+        let backup = self.dir.join(format!("{}.orig", Uuid::new_v4()));
+        tokio::fs::write(&backup, original)
+            .await
+            .with_context(|| format!("Could not back up file '{}'", path.display()))?;
+
+        self.files.push(BackupEntry {
+            path: path.to_owned(),
+            backup,
+            replaced_hash: content_hash(replaced.as_bytes()),
+        });
+        Ok(())
+    }
+
+    // forgets the entries of a file whose write failed, it still holds its
+    // original content so undo must not expect the replaced one
+    pub async fn discard(&mut self, path: &Path) {
+        let (discarded, kept) = std::mem::take(&mut self.files)
+            .into_iter()
+            .partition(|entry| entry.path == path);
+        self.files = kept;
+        for entry in discarded {
+            let _ = tokio::fs::remove_file(&entry.backup).await;
+        }
+    }
+
+    // deletes the saved originals once the replace can no longer be undone
+    pub async fn remove(self) {
+        let _ = tokio::fs::remove_dir_all(&self.dir).await;
+    }
+
+    // undoes the renames and restores every file, refusing to touch anything
+    // if one of them was modified or moved after the replace
+    pub async fn restore(self) -> Result<Vec<PathBuf>, Error> {
+        let mut changed = vec![];
+        for entry in self.files.iter() {
+            let path = self.current_path(&entry.path);
+            let current = tokio::fs::read(&path)
+                .await
+                .with_context(|| format!("Could not read file: '{}'", path.display()))?;
+            if content_hash(&current) != entry.replaced_hash {
+                changed.push(path);
+            }
+        }
+        for rename in self.renames.iter() {
+            if tokio::fs::symlink_metadata(&rename.from).await.is_ok() {
+                changed.push(rename.from.clone());
+            }
+        }
+
+        if !changed.is_empty() {
+            let changed: Vec<String> = changed
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            bail!(
+                "Refusing to undo, the following files changed since the replace:\n\n- '{}'",
+                changed.join("'\n- '")
+            );
+        }
+
+        let mut restored = vec![];
+        for rename in self.renames.iter().rev() {
+            tokio::fs::rename(&rename.to, &rename.from)
+                .await
+                .with_context(|| format!("Could not rename '{}' back", rename.to.display()))?;
+            restored.push(rename.from.clone());
+        }
+        for entry in self.files.iter() {
+            let original = tokio::fs::read(&entry.backup)
+                .await
+                .with_context(|| format!("Could not read backup of '{}'", entry.path.display()))?;
+            write_atomic(&entry.path, &original)
+                .await
+                .with_context(|| format!("Could not restore file '{}'", entry.path.display()))?;
+            restored.push(entry.path.clone());
+        }
+
+        let _ = tokio::fs::remove_dir_all(&self.dir).await;
+
+        Ok(restored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Backup;
+    use std::fs;
+
+    #[tokio::test]
+    async fn restore_refuses_changed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("text.txt");
+
+        fs::write(&path, "new").unwrap();
+        let mut backup = Backup::in_dir(dir.path());
+        backup.save(&path, "old", "new").await.unwrap();
+
+        // edited after the replace, so undo must not overwrite it
+        fs::write(&path, "edited").unwrap();
+        assert!(backup.clone().restore().await.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "edited");
+
+        fs::write(&path, "new").unwrap();
+        let restored = backup.restore().await.unwrap();
+        assert_eq!(restored, vec![path.clone()]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+    }
+}
@@ -0,0 +1,255 @@
+use anyhow::{bail, Context, Error, Result};
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::backup::Backup;
+use crate::diff::diff_from_vec;
+use crate::dir_crawl::{dir_crawl, CrawlOptions, FileFilter, DEFAULT_FILTER};
+use crate::find_and_replace::{
+    find_from_vec, replace_from_vec, FileReport, NothingFound, ReplaceTarget, Rule, SearchOptions,
+    DEFAULT_WORKERS,
+};
+use crate::history::{append_entry, history_path, HistoryEntry};
+use crate::presenter::{describe_rules, plain_file, plain_rename, plain_report};
+use crate::rename::{check_conflicts, find_renames, rename_all, Rename};
+
+// command line interface, when no subcommand is given the GUI is started
+#[derive(Parser, Debug)]
+#[command(version, about = "Recursive find and replace")]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Show every occurrence of the pattern without modifying any file
+    Find {
+        #[command(flatten)]
+        params: Params,
+        /// Print the matches as JSON
+        #[arg(long)]
+        json: bool,
+        /// Print the changes as a unified diff, apply it from --dir with `patch -p0`
+        #[arg(long, conflicts_with = "json")]
+        diff: bool,
+    },
+    /// Replace every occurrence of the pattern in place
+    Replace(Params),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Params {
+    /// Pattern to search for, repeat together with --replace for several rules
+    #[arg(short, long, required = true)]
+    pub find: Vec<String>,
+    /// Text to replace the pattern with, one for every --find
+    #[arg(short, long, required = true)]
+    pub replace: Vec<String>,
+    /// Directory to search recursively, relative to the working directory unless absolute
+    #[arg(short, long)]
+    pub dir: PathBuf,
+    /// Comma separated glob patterns of files to include, prefix with `!` to exclude
+    #[arg(long, default_value = DEFAULT_FILTER)]
+    pub filter: String,
+    /// Also search hidden files and directories, they are skipped by default
+    #[arg(long)]
+    pub hidden: bool,
+    /// Do not honour the .gitignore, .ignore and .frrignore files below --dir, they are honoured
+    /// by default
+    #[arg(long)]
+    pub no_ignore: bool,
+    /// Name of a directory that is never entered, may be repeated
+    #[arg(long = "exclude-dir", value_name = "NAME")]
+    pub exclude_dirs: Vec<String>,
+    /// Skip symlinks instead of searching the files and directories they point to
+    #[arg(long)]
+    pub no_follow: bool,
+    /// Number of directory levels to descend into, 1 only searches --dir itself
+    #[arg(long)]
+    pub max_depth: Option<usize>,
+    /// Treat the pattern as a regular expression, the replacement may use `$1` or `${name}`
+    #[arg(short = 'E', long)]
+    pub regex: bool,
+    /// Match regardless of upper and lower case
+    #[arg(short = 'i', long)]
+    pub ignore_case: bool,
+    /// Only match the pattern when it is not part of a longer word
+    #[arg(short = 'w', long)]
+    pub word: bool,
+    /// Interpret `\n` and `\t` in the pattern and replacement as line breaks and tabs
+    #[arg(short = 'm', long)]
+    pub multiline: bool,
+    /// Also rename files and directories below --dir whose names match, regardless of --filter
+    #[arg(long)]
+    pub rename: bool,
+    /// Number of files searched or replaced at the same time
+    #[arg(long, default_value_t = DEFAULT_WORKERS)]
+    pub workers: usize,
+}
+
+impl Params {
+    // pairs up the n-th --find with the n-th --replace
+    fn rules(&self) -> Result<Vec<Rule>, Error> {
+        if self.find.len() != self.replace.len() {
+            bail!("Every --find needs a matching --replace");
+        }
+        Ok(self
+            .find
+            .iter()
+            .zip(self.replace.iter())
+            .map(|(find, replace)| Rule::new(find, replace))
+            .collect())
+    }
+
+    fn renames(&self, rules: &[Rule]) -> Result<Vec<Rename>, Error> {
+        if !self.rename {
+            return Ok(vec![]);
+        }
+        let (renames, skipped) =
+            find_renames(&self.root()?, rules, &self.options(), &self.crawl_options())?;
+        if skipped > 0 {
+            eprintln!(
+                "Skipped {} entries that could not be read for renaming",
+                skipped
+            );
+        }
+        Ok(renames)
+    }
+
+    fn crawl_options(&self) -> CrawlOptions {
+        CrawlOptions {
+            skip_hidden: !self.hidden,
+            ignore_files: !self.no_ignore,
+            exclude_dirs: self.exclude_dirs.clone(),
+            follow_links: !self.no_follow,
+            max_depth: self.max_depth,
+        }
+    }
+
+    // --dir resolved against the working directory, canonical like the crawled
+    // files so renames and backups agree
+    fn root(&self) -> Result<PathBuf, Error> {
+        std::fs::canonicalize(&self.dir)
+            .with_context(|| format!("Invalid directory '{}'", self.dir.display()))
+    }
+
+    // skipped entries are reported but do not stop the search
+    fn files(&self) -> Result<Vec<PathBuf>, Error> {
+        let (files, skipped) = dir_crawl(
+            &self.root()?,
+            &FileFilter::parse(&self.filter)?,
+            &self.crawl_options(),
+        )?;
+        if skipped > 0 {
+            eprintln!("Skipped {} entries that could not be read", skipped);
+        }
+        Ok(files)
+    }
+
+    fn options(&self) -> SearchOptions {
+        SearchOptions {
+            regex: self.regex,
+            case_insensitive: self.ignore_case,
+            whole_word: self.word,
+            multiline: self.multiline,
+        }
+    }
+}
+
+pub async fn run(command: Command) -> Result<(), Error> {
+    match command {
+        Command::Find { params, json, diff } => {
+            let file_list = params.files()?;
+            let rules = params.rules()?;
+            if diff {
+                let targets = file_list.into_iter().map(ReplaceTarget::from).collect();
+                let (patch, skipped) =
+                    diff_from_vec(rules, targets, params.options(), params.root()?).await?;
+                print!("{}", patch);
+                let failed = plain_report(&skipped);
+                if !failed.is_empty() {
+                    eprintln!(
+                        "The following files were left out of the diff:\n{}",
+                        failed.trim_end()
+                    );
+                }
+                return Ok(());
+            }
+            let renames = params.renames(&rules)?;
+            let (files, reports) =
+                match find_from_vec(rules, file_list, params.options(), params.workers).await {
+                    Ok(found) => found,
+                    // only names matched
+                    Err(e) if e.is::<NothingFound>() && !renames.is_empty() => (vec![], vec![]),
+                    Err(e) => return Err(e),
+                };
+            if json && params.rename {
+                let output = serde_json::json!({ "files": files, "renames": renames });
+                println!("{}", serde_json::to_string_pretty(&output)?);
+            } else if json {
+                println!("{}", serde_json::to_string_pretty(&files)?);
+            } else {
+                let text: String = files
+                    .iter()
+                    .map(plain_file)
+                    .chain(renames.iter().map(plain_rename))
+                    .collect();
+                println!("{}", text.trim());
+            }
+            let failed = plain_report(&reports);
+            if !failed.is_empty() {
+                eprintln!("The following files were skipped:\n{}", failed.trim_end());
+            }
+        }
+        Command::Replace(params) => {
+            let file_list = params.files()?;
+            let rules = params.rules()?;
+            // refuse colliding renames before any content is changed
+            let renames = params.renames(&rules)?;
+            check_conflicts(&renames)?;
+            let (text, mut backup, reports) = replace_from_vec(
+                rules.clone(),
+                file_list.into_iter().map(ReplaceTarget::from).collect(),
+                params.options(),
+                params.workers,
+                Backup::new(),
+            )
+            .await?;
+            println!(
+                "Replaced {} in the following files: {}",
+                describe_rules(&rules),
+                text
+            );
+            let failed = plain_report(&reports);
+            if !failed.is_empty() {
+                eprintln!("The following files were skipped:\n{}", failed.trim_end());
+            }
+            if params.rename {
+                let (renamed, rename_reports) = rename_all(renames, &mut backup).await?;
+                println!("Renamed the following files and directories: {}", renamed);
+                let failed = plain_report(&rename_reports);
+                if !failed.is_empty() {
+                    eprintln!("The following renames failed:\n{}", failed.trim_end());
+                }
+            }
+            if !backup.is_empty() {
+                println!("Original files were backed up to {:?}", backup.dir);
+            }
+            // the files were already changed, so a failing journal is only reported
+            let entry = HistoryEntry::new(
+                &params.root()?,
+                rules,
+                reports
+                    .iter()
+                    .filter_map(FileReport::replaced_file)
+                    .collect(),
+                backup.renames.clone(),
+            );
+            if let Err(e) = async { append_entry(history_path()?, entry).await }.await {
+                eprintln!("Could not record the replace in the history: {:#}", e);
+            }
+        }
+    }
+    Ok(())
+}
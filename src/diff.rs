@@ -0,0 +1,337 @@
+use anyhow::{bail, Context, Error, Result};
+use std::path::PathBuf;
+
+use crate::backup::content_hash;
+use crate::find_and_replace::{
+    apply_matches, compile_rules, selected_matches, ChangedSincePreview, FileReport, ReplaceTarget,
+    Rule, RuleMatch, SearchOptions,
+};
+
+// unchanged lines shown around every change
+pub const DIFF_CONTEXT: usize = 3;
+
+// a run of whole lines `old_start..old_end` (0-based) replaced by `new`
+struct Change {
+    old_start: usize,
+    old_end: usize,
+    new: String,
+}
+
+fn push_line(output: &mut String, prefix: char, line: &str) {
+    output.push(prefix);
+    output.push_str(line);
+    if !line.ends_with('\n') {
+        output.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+// hunk headers count lines from 1, an empty range points at the line before it
+fn hunk_range(start: usize, count: usize) -> String {
+    if count == 0 {
+        format!("{},0", start)
+    } else {
+        format!("{},{}", start + 1, count)
+    }
+}
+
+// unified diff of replacing `matches` in `text`, `label` is the path written
+// into the `---`/`+++` headers
+pub(crate) fn unified_diff(
+    label: &str,
+    text: &str,
+    matches: &[RuleMatch],
+    context: usize,
+) -> String {
+    if matches.is_empty() {
+        return "".to_owned();
+    }
+
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let mut starts: Vec<usize> = lines
+        .iter()
+        .scan(0, |offset, line| {
+            let start = *offset;
+            *offset += line.len();
+            Some(start)
+        })
+        .collect();
+    starts.push(text.len());
+    let line_of = |offset: usize| {
+        starts[..lines.len()]
+            .partition_point(|&start| start <= offset)
+            .saturating_sub(1)
+    };
+
+    // matches touching the same lines form a single change
+    let mut groups: Vec<(usize, usize, Vec<&RuleMatch>)> = vec![];
+    for m in matches {
+        let first = line_of(m.range.start);
+        let mut last = line_of(m.range.end.max(m.range.start + 1) - 1);
+        // a match swallowing a line ending joins the next line unless the
+        // replacement brings its own
+        if m.range.end > m.range.start
+            && starts[line_of(m.range.end)] == m.range.end
+            && !m.after.ends_with('\n')
+        {
+            last = line_of(m.range.end);
+        }
+        match groups.last_mut() {
+            Some(group) if first < group.1 => {
+                group.1 = group.1.max(last + 1);
+                group.2.push(m);
+            }
+            _ => groups.push((first, last + 1, vec![m])),
+        }
+    }
+    let changes: Vec<Change> = groups
+        .into_iter()
+        .map(|(first, end, matches)| {
+            let (first, end) = (first.min(lines.len()), end.min(lines.len()));
+            let offset = starts[first];
+            let shifted: Vec<RuleMatch> = matches
+                .iter()
+                .map(|m| RuleMatch {
+                    rule: m.rule,
+                    range: m.range.start - offset..m.range.end - offset,
+                    after: m.after.clone(),
+                })
+                .collect();
+            Change {
+                old_start: first,
+                old_end: end,
+                new: apply_matches(&text[offset..starts[end]], &shifted),
+            }
+        })
+        .collect();
+
+    let mut output = format!("--- {}\n+++ {}\n", label, label);
+    let mut delta: isize = 0;
+    let mut index = 0;
+    while index < changes.len() {
+        // changes closer than twice the context share a hunk
+        let mut last = index;
+        while last + 1 < changes.len()
+            && changes[last + 1].old_start - changes[last].old_end <= 2 * context
+        {
+            last += 1;
+        }
+        let hunk = &changes[index..=last];
+        let old_from = hunk[0].old_start.saturating_sub(context);
+        let old_to = (hunk[hunk.len() - 1].old_end + context).min(lines.len());
+
+        let mut body = "".to_owned();
+        let mut new_count = 0;
+        let mut line = old_from;
+        for change in hunk {
+            for context_line in &lines[line..change.old_start] {
+                push_line(&mut body, ' ', context_line);
+                new_count += 1;
+            }
+            for old_line in &lines[change.old_start..change.old_end] {
+                push_line(&mut body, '-', old_line);
+            }
+            for new_line in change.new.split_inclusive('\n') {
+                push_line(&mut body, '+', new_line);
+                new_count += 1;
+            }
+            line = change.old_end;
+        }
+        for context_line in &lines[line..old_to] {
+            push_line(&mut body, ' ', context_line);
+            new_count += 1;
+        }
+
+        let old_count = old_to - old_from;
+        let new_from = (old_from as isize + delta) as usize;
+        output.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_from, old_count),
+            hunk_range(new_from, new_count)
+        ));
+        output.push_str(&body);
+
+        delta += new_count as isize - old_count as isize;
+        index = last + 1;
+    }
+    output
+}
+
+// the selected lines only make sense for the content that was previewed, the
+// same check the replace does
+async fn read_target(target: &ReplaceTarget) -> Result<String, Error> {
+    let reader = tokio::fs::read_to_string(&target.path)
+        .await
+        .with_context(|| format!("Could not read file: '{}'", target.path.display()))?;
+    if target
+        .hash
+        .is_some_and(|hash| hash != content_hash(reader.as_bytes()))
+    {
+        bail!(ChangedSincePreview(target.path.clone()));
+    }
+    Ok(reader)
+}
+
+// a patch of every pending change with paths relative to `root`, so it can be
+// applied from there with `patch -p0` or `git apply -p0`, renames are not part
+// of it. files that can't be read or changed since the preview are left out
+// and reported instead
+pub async fn diff_from_vec(
+    rules: Vec<Rule>,
+    targets: Vec<ReplaceTarget>,
+    options: SearchOptions,
+    root: PathBuf,
+) -> Result<(String, Vec<FileReport>), Error> {
+    let rules = compile_rules(&rules, &options)?;
+    let root = std::fs::canonicalize(&root).unwrap_or(root);
+    let mut output = "".to_owned();
+    let mut skipped = vec![];
+
+    for target in targets.into_iter() {
+        let reader = match read_target(&target).await {
+            Ok(reader) => reader,
+            Err(e) => {
+                skipped.push(FileReport::failed(target.path, &e));
+                continue;
+            }
+        };
+        let matches = selected_matches(&rules, &reader, target.lines.as_ref(), &options);
+        let label = target
+            .path
+            .strip_prefix(&root)
+            .unwrap_or(&target.path)
+            .display()
+            .to_string();
+        output.push_str(&unified_diff(&label, &reader, &matches, DIFF_CONTEXT));
+    }
+    Ok((output, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff_from_vec;
+    use crate::backup::content_hash;
+    use crate::find_and_replace::{FileStatus, ReplaceTarget, Rule, SearchOptions};
+    use std::fs;
+
+    #[tokio::test]
+    async fn diff_from_vec_writes_hunks_with_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let lines: Vec<String> = (1..=12).map(|n| format!("line {}", n)).collect();
+        let contents = format!("izak\n{}\nizak", lines.join("\n"));
+        let path = dir.path().join("a.txt");
+        fs::write(&path, &contents).unwrap();
+
+        let patch = diff_from_vec(
+            vec![Rule::new("izak", "tina")],
+            vec![ReplaceTarget::from(path.clone())],
+            SearchOptions::default(),
+            dir.path().to_owned(),
+        )
+        .await
+        .unwrap()
+        .0;
+
+        let expected = "--- a.txt\n+++ a.txt\n\
+            @@ -1,4 +1,4 @@\n-izak\n+tina\n line 1\n line 2\n line 3\n\
+            @@ -11,4 +11,4 @@\n line 10\n line 11\n line 12\n-izak\n\\ No newline at end of file\n\
+            +tina\n\\ No newline at end of file\n";
+        assert_eq!(patch, expected);
+        // nothing is written while diffing
+        assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+    }
+
+    #[tokio::test]
+    async fn diff_from_vec_joins_lines_of_multi_line_matches() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.params");
+        fs::write(&path, "[run]\r\nname=E0-0_01\r\nend\r\n").unwrap();
+
+        let patch = diff_from_vec(
+            vec![Rule::new("[run]\\nname=E0-0_01", "name=E0-1_01")],
+            vec![ReplaceTarget::from(path.clone())],
+            SearchOptions {
+                multiline: true,
+                ..SearchOptions::default()
+            },
+            dir.path().to_owned(),
+        )
+        .await
+        .unwrap()
+        .0;
+
+        assert_eq!(
+            patch,
+            "--- a.params\n+++ a.params\n@@ -1,3 +1,2 @@\n-[run]\r\n-name=E0-0_01\r\n+name=E0-1_01\r\n end\r\n"
+        );
+
+        // removing a line break pulls the next line into the change
+        let patch = diff_from_vec(
+            vec![Rule::new("_01\r\n", " ")],
+            vec![ReplaceTarget::from(path.clone())],
+            SearchOptions {
+                regex: true,
+                ..SearchOptions::default()
+            },
+            dir.path().to_owned(),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert!(patch.ends_with("-name=E0-0_01\r\n-end\r\n+name=E0-0 end\r\n"));
+    }
+
+    #[tokio::test]
+    async fn diff_from_vec_skips_files_it_can_not_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = dir.path().join("a.bin");
+        fs::write(&binary, [0x69, 0xff, 0x7a]).unwrap();
+        let path = dir.path().join("b.txt");
+        fs::write(&path, "izak\n").unwrap();
+
+        let (patch, skipped) = diff_from_vec(
+            vec![Rule::new("izak", "tina")],
+            vec![
+                ReplaceTarget::from(binary.clone()),
+                ReplaceTarget::from(path.clone()),
+            ],
+            SearchOptions::default(),
+            dir.path().to_owned(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            patch,
+            "--- b.txt\n+++ b.txt\n@@ -1,1 +1,1 @@\n-izak\n+tina\n"
+        );
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].path, binary);
+        assert_eq!(skipped[0].status, FileStatus::NotUtf8);
+    }
+
+    #[tokio::test]
+    async fn diff_from_vec_skips_files_changed_since_the_preview() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        fs::write(&path, "izak\nizak\n").unwrap();
+        let target = ReplaceTarget {
+            path: path.clone(),
+            lines: Some(vec![2]),
+            hash: Some(content_hash(b"izak\nizak\n")),
+        };
+        // the first line is removed, so line 2 no longer exists
+        fs::write(&path, "izak\n").unwrap();
+
+        let (patch, skipped) = diff_from_vec(
+            vec![Rule::new("izak", "tina")],
+            vec![target],
+            SearchOptions::default(),
+            dir.path().to_owned(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(patch, "");
+        assert_eq!(skipped[0].status, FileStatus::Changed);
+    }
+}
@@ -1,54 +1,458 @@
 use anyhow::Error;
 use anyhow::{Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fs;
+use std::path::{Path, PathBuf};
 
-pub fn dir_crawl(org_path: &str) -> Result<Vec<String>, Error> {
-    let paths = fs::read_dir(org_path)?;
+use crate::find_and_replace::CancelFlag;
 
-    let mut list: Vec<String> = vec![];
+// include/exclude glob patterns deciding which files get collected,
+// parsed from a comma separated list like `*.txt,*.params,!*.bak`
+#[derive(Debug, Clone)]
+pub struct FileFilter {
+    include: GlobSet,
+    exclude: GlobSet,
+    include_all: bool,
+}
+
+impl FileFilter {
+    pub fn parse(patterns: &str) -> Result<FileFilter, Error> {
+        let mut include = GlobSetBuilder::new();
+        let mut exclude = GlobSetBuilder::new();
+        let mut include_all = true;
+
+        for pattern in patterns.split(',').map(str::trim) {
+            if pattern.is_empty() {
+                continue;
+            }
+            match pattern.strip_prefix('!') {
+                Some(pattern) => {
+                    exclude.add(
+                        Glob::new(pattern)
+                            .with_context(|| format!("Invalid file filter: '!{}'", pattern))?,
+                    );
+                }
+                None => {
+                    include_all = false;
+                    include.add(
+                        Glob::new(pattern)
+                            .with_context(|| format!("Invalid file filter: '{}'", pattern))?,
+                    );
+                }
+            }
+        }
+
+        Ok(FileFilter {
+            include: include.build()?,
+            exclude: exclude.build()?,
+            include_all,
+        })
+    }
+
+    // a filter with only exclusions collects every other file
+    pub fn is_match(&self, path: &Path) -> bool {
+        (self.include_all || self.include.is_match(path)) && !self.exclude.is_match(path)
+    }
+}
+
+impl Default for FileFilter {
+    fn default() -> Self {
+        FileFilter::parse(DEFAULT_FILTER).expect("default file filter is valid")
+    }
+}
+
+pub const DEFAULT_FILTER: &str = "*.txt";
+
+// name of the ignore file read in addition to `.gitignore` and `.ignore`
+pub const IGNORE_FILE: &str = ".frrignore";
+
+// which entries the walk skips regardless of the file filter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CrawlOptions {
+    // entries whose name starts with a dot, e.g. `.git`
+    pub skip_hidden: bool,
+    // honour `.gitignore`, `.ignore` and `.frrignore` files
+    pub ignore_files: bool,
+    // names of directories that are never entered, e.g. `target`
+    pub exclude_dirs: Vec<String>,
+    // search the targets of symlinks, otherwise symlinks are skipped entirely
+    pub follow_links: bool,
+    // how many directory levels are entered, 1 only searches the directory
+    // itself, `None` has no limit
+    pub max_depth: Option<usize>,
+}
+
+impl Default for CrawlOptions {
+    fn default() -> Self {
+        CrawlOptions {
+            skip_hidden: true,
+            ignore_files: true,
+            exclude_dirs: vec![],
+            follow_links: true,
+            max_depth: None,
+        }
+    }
+}
+
+// directory names from a comma separated list like `target, node_modules`
+pub fn parse_dir_list(names: &str) -> Vec<String> {
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+// a walk below `root` leaving out what `crawl` excludes, shared by the file
+// search and the renames
+pub(crate) fn walker(root: &Path, crawl: &CrawlOptions) -> WalkBuilder {
+    let exclude_dirs = crawl.exclude_dirs.clone();
+    let mut walker = WalkBuilder::new(root);
+    walker
+        .hidden(crawl.skip_hidden)
+        .ignore(crawl.ignore_files)
+        .git_ignore(crawl.ignore_files)
+        .git_global(crawl.ignore_files)
+        .git_exclude(crawl.ignore_files)
+        // the searched trees are rarely git checkouts themselves, so only the
+        // ignore files below `root` count, not those of its parent directories
+        .require_git(false)
+        .parents(false)
+        .max_depth(crawl.max_depth)
+        .filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|kind| kind.is_dir());
+            !(is_dir
+                && entry.depth() > 0
+                && exclude_dirs
+                    .iter()
+                    .any(|name| entry.file_name() == name.as_str()))
+        });
+    if crawl.ignore_files {
+        walker.add_custom_ignore_filename(IGNORE_FILE);
+    }
+    walker
+}
 
-    for path in paths {
-        let path = path?;
-        let is_dir = &path.path().is_dir();
+// a symlink pointing back to one of its own parent directories, the walker
+// recognises those by comparing device and inode with every ancestor
+fn is_loop(e: &ignore::Error) -> bool {
+    match e {
+        ignore::Error::Loop { .. } => true,
+        ignore::Error::WithPath { err, .. } | ignore::Error::WithDepth { err, .. } => is_loop(err),
+        _ => false,
+    }
+}
+
+// depth of the entry an error belongs to, the root has depth 0
+pub(crate) fn error_depth(e: &ignore::Error) -> Option<usize> {
+    match e {
+        ignore::Error::WithDepth { depth, .. } => Some(*depth),
+        ignore::Error::WithPath { err, .. } => error_depth(err),
+        _ => None,
+    }
+}
 
-        // if a directory, recursively call find_and_replace
-        if is_dir.to_owned() {
-            for path in dir_crawl(&path.path().display().to_string())
-                .with_context(|| format!("Could not enter {:?}", &path))?
-            {
-                list.push(path.clone());
+// the files below a directory, yielded while walking, so only the open
+// directories are held in memory instead of the whole tree
+pub struct CrawlFiles {
+    root: PathBuf,
+    walk: ignore::Walk,
+    filter: FileFilter,
+    // canonical paths already yielded, only needed when following symlinks
+    seen: Option<HashSet<PathBuf>>,
+    // ends the walk early, checked before every entry
+    cancel: CancelFlag,
+    // entries below the root that could not be read, e.g. directories without
+    // permission or dangling symlinks
+    pub skipped: usize,
+}
+
+impl Iterator for CrawlFiles {
+    // only a root that can not be walked is an error
+    type Item = Result<PathBuf, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for entry in self.walk.by_ref() {
+            if self.cancel.is_cancelled() {
+                return None;
+            }
+            let entry = match entry {
+                Ok(entry) => entry,
+                // the tree below the symlink is walked already
+                Err(e) if is_loop(&e) => continue,
+                Err(e) if error_depth(&e).is_some_and(|depth| depth > 0) => {
+                    self.skipped += 1;
+                    continue;
+                }
+                Err(e) => {
+                    let root = &self.root;
+                    return Some(Err(e).with_context(|| format!("Could not walk {:?}", root)));
+                }
+            };
+            // without following, symlinks are neither searched nor entered
+            let Some(kind) = entry.file_type() else {
+                continue;
+            };
+            if kind.is_dir() || kind.is_symlink() || !self.filter.is_match(entry.path()) {
+                continue;
+            }
+            let Ok(can_path) = fs::canonicalize(entry.path()) else {
+                self.skipped += 1;
+                continue;
+            };
+            // a file reached through several symlinks is only yielded once
+            if let Some(seen) = self.seen.as_mut() {
+                if !seen.insert(can_path.clone()) {
+                    continue;
+                }
             }
-        } else if path.path().display().to_string().ends_with(".txt") {
-            let can_path = fs::canonicalize(path.path().display().to_string())?
-                .display()
-                .to_string();
-            list.push(can_path);
+            return Some(Ok(can_path));
         }
+        None
     }
-    Ok(list)
 }
 
+pub fn crawl_files(org_path: &Path, filter: &FileFilter, crawl: &CrawlOptions) -> CrawlFiles {
+    CrawlFiles {
+        root: org_path.to_owned(),
+        walk: walker(org_path, crawl)
+            .follow_links(crawl.follow_links)
+            .build(),
+        filter: filter.clone(),
+        seen: crawl.follow_links.then(HashSet::new),
+        cancel: CancelFlag::default(),
+        skipped: 0,
+    }
+}
+
+impl CrawlFiles {
+    // stops yielding files once `cancel` is set, the files found so far are kept
+    pub fn with_cancel(mut self, cancel: CancelFlag) -> Self {
+        self.cancel = cancel;
+        self
+    }
+}
+
+// every file below `org_path` passing `filter` as canonical paths, together
+// with the number of entries that had to be skipped
+pub fn dir_crawl(
+    org_path: &Path,
+    filter: &FileFilter,
+    crawl: &CrawlOptions,
+) -> Result<(Vec<PathBuf>, usize), Error> {
+    let mut files = crawl_files(org_path, filter, crawl);
+    let list = files.by_ref().collect::<Result<Vec<PathBuf>, Error>>()?;
+    Ok((list, files.skipped))
+}
+
+// runs the blocking walk on a thread of its own, so it does not stall the
+// caller, e.g. the GUI, setting `cancel` stops the walk
+pub async fn dir_crawl_async(
+    org_path: PathBuf,
+    filter: FileFilter,
+    crawl: CrawlOptions,
+    cancel: CancelFlag,
+) -> Result<(Vec<PathBuf>, usize), Error> {
+    tokio::task::spawn_blocking(move || {
+        let mut files = crawl_files(&org_path, &filter, &crawl).with_cancel(cancel);
+        let list = files.by_ref().collect::<Result<Vec<PathBuf>, Error>>()?;
+        Ok((list, files.skipped))
+    })
+    .await?
+}
+
+#[cfg(test)]
 mod tests {
     use std::fs;
+    use std::path::Path;
 
-    use super::dir_crawl;
+    use super::{crawl_files, dir_crawl, CrawlOptions, FileFilter};
+    use crate::find_and_replace::CancelFlag;
 
     #[test]
     fn dir_crawl_is_ok() {
-        let result = dir_crawl(".");
+        // relative paths are resolved against the working directory
+        let result = dir_crawl(
+            Path::new("."),
+            &FileFilter::default(),
+            &CrawlOptions::default(),
+        );
 
         assert!(result.is_ok());
     }
 
     #[test]
     fn dir_crawl_finds_created_file() {
-        let create = fs::File::create(".dir_crawl.txt");
-        assert!(create.is_ok());
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("Search_1")).unwrap();
+        fs::write(dir.path().join("Search_1/parameters.txt"), "").unwrap();
+        fs::write(dir.path().join("Search_1/run.mzXML"), "").unwrap();
+
+        let (result, skipped) =
+            dir_crawl(dir.path(), &FileFilter::default(), &CrawlOptions::default()).unwrap();
+        let expected = fs::canonicalize(dir.path().join("Search_1/parameters.txt")).unwrap();
+        assert_eq!(skipped, 0);
+        assert_eq!(result, vec![expected]);
+    }
+
+    #[test]
+    fn cancelled_crawl_stops_walking() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "").unwrap();
+        let cancel = CancelFlag::default();
+        cancel.cancel();
+
+        let mut files = crawl_files(dir.path(), &FileFilter::default(), &CrawlOptions::default())
+            .with_cancel(cancel);
+
+        assert!(files.next().is_none());
+    }
+
+    #[test]
+    fn dir_crawl_fails_on_missing_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+
+        assert!(dir_crawl(&missing, &FileFilter::default(), &CrawlOptions::default()).is_err());
+    }
+
+    #[test]
+    fn dir_crawl_skips_hidden_ignored_and_excluded_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        for sub in [".git", "target", "data", "logs"] {
+            fs::create_dir_all(dir.path().join(sub)).unwrap();
+            fs::write(dir.path().join(sub).join("a.txt"), "").unwrap();
+        }
+        fs::write(dir.path().join(".hidden.txt"), "").unwrap();
+        fs::write(dir.path().join(".gitignore"), "logs/\n").unwrap();
+        fs::write(dir.path().join(".frrignore"), "data/b.txt\n").unwrap();
+        fs::write(dir.path().join("data/b.txt"), "").unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let names = |crawl: &CrawlOptions| {
+            let mut names: Vec<String> = dir_crawl(&root, &FileFilter::default(), crawl)
+                .unwrap()
+                .0
+                .into_iter()
+                .map(|path| {
+                    let path = path.strip_prefix(&root).unwrap();
+                    path.display().to_string()
+                })
+                .collect();
+            names.sort();
+            names
+        };
+
+        let crawl = CrawlOptions {
+            exclude_dirs: vec!["target".to_owned()],
+            ..CrawlOptions::default()
+        };
+        assert_eq!(names(&crawl), vec!["data/a.txt"]);
+
+        let everything = CrawlOptions {
+            skip_hidden: false,
+            ignore_files: false,
+            exclude_dirs: vec![],
+            follow_links: false,
+            max_depth: None,
+        };
+        assert_eq!(names(&everything).len(), 6);
+    }
+
+    #[test]
+    fn dir_crawl_ignores_ignore_files_above_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("data")).unwrap();
+        fs::write(dir.path().join(".gitignore"), "*.txt\n").unwrap();
+        fs::write(dir.path().join("data/a.txt"), "").unwrap();
+
+        let (result, _) = dir_crawl(
+            &dir.path().join("data"),
+            &FileFilter::default(),
+            &CrawlOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(result.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dir_crawl_survives_symlink_loops_and_lists_files_once() {
+        use std::ffi::OsStr;
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a")).unwrap();
+        fs::write(dir.path().join("a/x.txt"), "").unwrap();
+        fs::write(outside.path().join("y.txt"), "").unwrap();
+        symlink(dir.path(), dir.path().join("a/loop")).unwrap();
+        symlink(dir.path().join("a/x.txt"), dir.path().join("link.txt")).unwrap();
+        symlink(outside.path(), dir.path().join("outside")).unwrap();
+        let root = dir.path();
+
+        let (followed, _) =
+            dir_crawl(root, &FileFilter::default(), &CrawlOptions::default()).unwrap();
+        let mut names: Vec<&OsStr> = followed
+            .iter()
+            .map(|path| path.file_name().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["x.txt", "y.txt"]);
+
+        let not_followed = CrawlOptions {
+            follow_links: false,
+            ..CrawlOptions::default()
+        };
+        let (found, _) = dir_crawl(root, &FileFilter::default(), &not_followed).unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].ends_with("a/x.txt"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dir_crawl_limits_depth_and_counts_unreadable_entries() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        fs::write(dir.path().join("top.txt"), "").unwrap();
+        fs::write(dir.path().join("a/b/deep.txt"), "").unwrap();
+        // following a dangling symlink fails, the rest of the tree is still walked
+        symlink(dir.path().join("missing"), dir.path().join("a/dangling")).unwrap();
+        let root = dir.path();
+
+        let (files, skipped) =
+            dir_crawl(root, &FileFilter::default(), &CrawlOptions::default()).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(skipped, 1);
+
+        let shallow = CrawlOptions {
+            max_depth: Some(1),
+            ..CrawlOptions::default()
+        };
+        let (files, skipped) = dir_crawl(root, &FileFilter::default(), &shallow).unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].ends_with("top.txt"));
+        assert_eq!(skipped, 0);
+    }
+
+    #[test]
+    fn file_filter_includes_and_excludes() {
+        let filter = FileFilter::parse("*.txt, *.params,!*.bak.txt").unwrap();
 
-        let result = dir_crawl(".").unwrap();
-        assert!(result.contains(&"/home/izak/dev/tina/Rust/frr/.dir_crawl.txt".to_owned()));
+        assert!(filter.is_match(Path::new("/data/search.params")));
+        assert!(filter.is_match(Path::new("/data/parameters.txt")));
+        assert!(!filter.is_match(Path::new("/data/parameters.bak.txt")));
+        assert!(!filter.is_match(Path::new("/data/run.mzXML")));
 
-        let remove = fs::remove_file(".dir_crawl.txt");
-        assert!(remove.is_ok())
+        let exclude_only = FileFilter::parse("!*.bak").unwrap();
+        assert!(exclude_only.is_match(Path::new("/data/run.mzXML")));
+        assert!(!exclude_only.is_match(Path::new("/data/run.bak")));
     }
 }
@@ -1,241 +1,1298 @@
+use crate::backup::{content_hash, Backup};
 use anyhow::{bail, Context, Error, Result};
+use futures::future::{ready, Future};
+use futures::stream::{self, Stream, StreamExt};
+use regex::{Captures, Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
 use std::{
     fs,
-    io::{self},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    sync::Arc,
 };
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
 
-// a function that will find all the occurrences of the pattern in a file (path)
-// and display them visually
+// options that change how the find pattern is interpreted, with `multiline`
+// the escapes `\n` and `\t` in the find and replace text stand for line
+// breaks and tabs, so patterns spanning lines can be typed into a single line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchOptions {
+    pub regex: bool,
+    pub case_insensitive: bool,
+    pub whole_word: bool,
+    pub multiline: bool,
+}
 
-pub async fn find_from_vec(
-    find_pat: String,
+// builds the regular expression used for matching, in plain mode the pattern
+// is escaped so it only matches itself
+pub fn build_pattern(find: &str, options: &SearchOptions) -> Result<Regex, Error> {
+    let mut pattern = if options.regex {
+        find.to_owned()
+    } else if options.multiline {
+        // a line break in a plain pattern also matches the CRLF endings
+        regex::escape(&unescape(find)).replace('\n', r"\r?\n")
+    } else {
+        regex::escape(find).replace('\n', r"\r?\n")
+    };
+
+    // half word boundaries only require that the match is not directly preceded
+    // or followed by a word character, so patterns starting or ending with
+    // punctuation like `-01` still work
+    if options.whole_word {
+        pattern = format!(r"\b{{start-half}}(?:{})\b{{end-half}}", pattern);
+    }
+
+    // the whole file is matched at once, `^` and `$` still anchor at every line,
+    // `match_rules` drops the empty line they would also find after a final newline
+    RegexBuilder::new(&pattern)
+        .case_insensitive(options.case_insensitive)
+        .multi_line(true)
+        .crlf(true)
+        .build()
+        .with_context(|| format!("Invalid regular expression: '{}'", find))
+}
+
+fn unescape(text: &str) -> String {
+    let mut output = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => output.push('\n'),
+            Some('t') => output.push('\t'),
+            Some('\\') => output.push('\\'),
+            Some(other) => {
+                output.push('\\');
+                output.push(other);
+            }
+            None => output.push('\\'),
+        }
+    }
+    output
+}
+
+fn prepare_replacement(replace: &str, options: &SearchOptions) -> String {
+    if options.multiline {
+        unescape(replace)
+    } else {
+        replace.to_owned()
+    }
+}
+
+// a single find/replace pair, an ordered list of them is applied in one pass
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    pub find: String,
+    pub replace: String,
+}
+
+impl Rule {
+    pub fn new(find: &str, replace: &str) -> Self {
+        Rule {
+            find: find.to_owned(),
+            replace: replace.to_owned(),
+        }
+    }
+}
+
+// a rule ready to be matched, compiled once and shared by every file
+#[derive(Debug, Clone)]
+pub struct CompiledRule {
+    pattern: Regex,
     replace: String,
-    paths: Vec<String>,
-) -> Result<String, Error> {
+}
+
+// fails on the first invalid pattern, before any file is searched
+pub fn compile_rules(rules: &[Rule], options: &SearchOptions) -> Result<Vec<CompiledRule>, Error> {
+    if rules.is_empty() {
+        bail!("No find pattern given");
+    }
+
+    rules
+        .iter()
+        .map(|rule| {
+            Ok(CompiledRule {
+                pattern: build_pattern(&rule.find, options)?,
+                replace: prepare_replacement(&rule.replace, options),
+            })
+        })
+        .collect()
+}
+
+// a match of one of the rules in the content, with its replacement expanded
+pub(crate) struct RuleMatch {
+    pub(crate) rule: usize,
+    pub(crate) range: Range<usize>,
+    pub(crate) after: String,
+}
+
+// all rules are matched against the original content, so one rule never sees
+// the output of another, matches are taken from left to right without
+// overlapping and when several rules match at the same position the earlier
+// rule wins
+fn match_rules(rules: &[CompiledRule], content: &str, options: &SearchOptions) -> Vec<RuleMatch> {
+    let newline = line_ending(content);
+    let mut found: Vec<RuleMatch> = rules
+        .iter()
+        .enumerate()
+        .flat_map(|(index, rule)| {
+            rule.pattern.captures_iter(content).map(move |caps| {
+                let m = caps.get(0).expect("capture group 0 is always present");
+                RuleMatch {
+                    rule: index,
+                    range: m.range(),
+                    after: expand_replacement(&caps, &rule.replace, options, newline),
+                }
+            })
+        })
+        // a final newline ends the last line instead of starting another one
+        .filter(|m| {
+            !(m.range.is_empty() && m.range.start == content.len() && content.ends_with('\n'))
+        })
+        .collect();
+    found.sort_by_key(|m| (m.range.start, m.rule));
+
+    let mut matches: Vec<RuleMatch> = vec![];
+    let mut last_end = 0;
+    for m in found {
+        let same_start = matches
+            .last()
+            .is_some_and(|last| last.range.start == m.range.start);
+        if m.range.start >= last_end && !same_start {
+            last_end = m.range.end;
+            matches.push(m);
+        }
+    }
+    matches
+}
+
+// applies the rules to a short text like a file name, None when nothing matched
+pub(crate) fn replace_in_text(
+    rules: &[CompiledRule],
+    text: &str,
+    options: &SearchOptions,
+) -> Option<String> {
+    let matches = match_rules(rules, text, options);
+    if matches.is_empty() {
+        return None;
+    }
+    Some(apply_matches(text, &matches))
+}
+
+// the matches of a file that are selected for replacing, `lines` limits them to
+// the ones starting on those lines, ranges include the byte order mark
+pub(crate) fn selected_matches(
+    rules: &[CompiledRule],
+    reader: &str,
+    lines: Option<&Vec<usize>>,
+    options: &SearchOptions,
+) -> Vec<RuleMatch> {
+    let (bom, content) = strip_bom(reader);
+    let index = LineIndex::new(content);
+    match_rules(rules, content, options)
+        .into_iter()
+        .filter(|m| lines.is_none_or(|lines| lines.contains(&index.line_of(m.range.start))))
+        .map(|m| RuleMatch {
+            range: bom.len() + m.range.start..bom.len() + m.range.end,
+            ..m
+        })
+        .collect()
+}
+
+// everything outside the replaced spans, line endings included, is copied
+// over unchanged
+pub(crate) fn apply_matches(text: &str, matches: &[RuleMatch]) -> String {
     let mut output = "".to_owned();
+    let mut last = 0;
+    for m in matches {
+        output.push_str(&text[last..m.range.start]);
+        output.push_str(&m.after);
+        last = m.range.end;
+    }
+    output.push_str(&text[last..]);
+    output
+}
+
+// expands the replacement for a single match, `$1` and `${name}` are only
+// substituted in regex mode, line breaks follow the ones used in the file
+fn expand_replacement(
+    caps: &Captures,
+    replace: &str,
+    options: &SearchOptions,
+    newline: &str,
+) -> String {
+    let mut expanded = String::new();
+    if options.regex {
+        caps.expand(replace, &mut expanded);
+    } else {
+        expanded.push_str(replace);
+    }
+    if newline == "\r\n" && expanded.contains('\n') {
+        expanded = expanded.replace("\r\n", "\n").replace('\n', "\r\n");
+    }
+    expanded
+}
+
+const BOM: &str = "\u{feff}";
 
-    for path in paths.into_iter() {
-        let f = find(find_pat.to_owned(), replace.to_owned(), path.to_string()).await?;
-        output = format!("{}{}", output, f);
+// splits off a UTF-8 byte order mark so it is neither matched nor lost
+fn strip_bom(content: &str) -> (&str, &str) {
+    match content.strip_prefix(BOM) {
+        Some(content) => (BOM, content),
+        None => ("", content),
     }
+}
 
-    if output == "" {
-        bail!(Box::new("Nothing found"));
+fn line_ending(content: &str) -> &'static str {
+    if content.contains("\r\n") {
+        "\r\n"
     } else {
-        Ok(output)
+        "\n"
     }
 }
 
-pub async fn find(find: String, replace: String, path: String) -> Result<String, Error> {
-    let path = path.to_owned();
-    let reader = tokio::fs::read_to_string(&path).await?;
-    let mut text = "".to_owned();
-    let mut file_contains_pattern = false;
+// splits the content into (line, line ending) pairs, keeping `\r\n`, `\n` or
+// a missing final newline so the file can be rebuilt byte for byte
+fn split_lines(content: &str) -> impl Iterator<Item = (&str, &str)> {
+    content.split_inclusive('\n').map(|line| {
+        let body = match line.strip_suffix('\n') {
+            Some(body) => body.strip_suffix('\r').unwrap_or(body),
+            None => line,
+        };
+        line.split_at(body.len())
+    })
+}
 
-    for (num, line) in reader.lines().enumerate() {
-        let unwraped_line: String = format!("{}", line);
+// byte range of every line (without its ending), used to map matches on the
+// whole content back to line numbers
+struct LineIndex {
+    lines: Vec<Range<usize>>,
+}
 
-        if unwraped_line.contains(&find) {
-            file_contains_pattern = true;
-            let display_line = display_line(&find, &replace, &unwraped_line, num + 1)
-                .expect("Line was not able to be displayed.");
-            text = format!("{}{}", text, display_line);
+impl LineIndex {
+    fn new(content: &str) -> Self {
+        let mut lines = vec![];
+        let mut offset = 0;
+        for (line, ending) in split_lines(content) {
+            lines.push(offset..offset + line.len());
+            offset += line.len() + ending.len();
+        }
+        if lines.is_empty() {
+            lines.push(0..0);
         }
+        LineIndex { lines }
     }
 
-    if file_contains_pattern {
-        text = format!("\n\n\n### File: '{}'\n\n\n{}", path, text);
+    // 1-based number of the line the byte at `offset` belongs to
+    fn line_of(&self, offset: usize) -> usize {
+        self.lines
+            .partition_point(|line| line.start <= offset)
+            .max(1)
     }
 
-    Ok(text)
+    fn range(&self, line: usize) -> &Range<usize> {
+        &self.lines[line - 1]
+    }
 }
 
-fn display_line(
-    find: &str,
-    replace: &str,
-    line: &str,
-    line_num: usize,
-) -> Result<String, io::Error> {
-    let mut output = format!("{}: \n", line_num);
+// paths are written lossily, as serde refuses paths that are not valid UTF-8
+pub(crate) fn serialize_path<S: serde::Serializer>(
+    path: &Path,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+// a single occurrence of the pattern of rule number `rule`, `byte_range` is
+// relative to the start of the file while `line` and `column` (in characters)
+// are 1-based, a match spanning line breaks ends on `end_line`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Match {
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    pub rule: usize,
+    pub line: usize,
+    pub end_line: usize,
+    pub column: usize,
+    pub byte_range: Range<usize>,
+    pub before: String,
+    pub after: String,
+}
+
+// the lines `line..=end_line` covered by one or more matches, `byte_offset` is
+// where `text` starts in the file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MatchedLine {
+    pub line: usize,
+    pub end_line: usize,
+    pub byte_offset: usize,
+    pub text: String,
+    pub matches: Vec<Match>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileMatches {
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    pub lines: Vec<MatchedLine>,
+    // hash of the content that was searched, lets the replace notice edits
+    // made after the preview
+    #[serde(skip)]
+    pub hash: u64,
+}
+
+impl FileMatches {
+    pub fn matches(&self) -> impl Iterator<Item = &Match> {
+        self.lines.iter().flat_map(|line| line.matches.iter())
+    }
+}
 
-    let highlight_old_line = highlight_pattern(find, line);
-    let new_line = line.replace(find, replace).clone();
-    let highlight_new_line = highlight_pattern(replace, &new_line);
+// a file to replace in, `lines` holds the (1-based) line numbers selected in
+// the preview, `None` replaces every match, a match is replaced when the line
+// it starts on is selected, with a `hash` the file is skipped when its content
+// no longer matches the one that was previewed
+#[derive(Debug, Clone)]
+pub struct ReplaceTarget {
+    pub path: PathBuf,
+    pub lines: Option<Vec<usize>>,
+    pub hash: Option<u64>,
+}
+
+impl From<PathBuf> for ReplaceTarget {
+    fn from(path: PathBuf) -> Self {
+        ReplaceTarget {
+            path,
+            lines: None,
+            hash: None,
+        }
+    }
+}
 
-    output = format!(
-        "{}\n{}\n\n => {}\n\n",
-        output, &highlight_old_line, &highlight_new_line
-    );
+// a file modified by a replace together with the number of replaced matches
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplacedFile {
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    pub matches: usize,
+}
 
-    Ok(output)
+// what happened to a single file during a search or replace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    Matched,
+    Replaced,
+    NoMatch,
+    Unreadable,
+    PermissionDenied,
+    NotUtf8,
+    Changed,
+    // file and directory names in rename mode
+    Renamed,
+    NotRenamed,
 }
 
-fn highlight_pattern(pattern: &str, line: &str) -> String {
-    let n = line.find(pattern);
-    match n {
-        Some(n) => {
-            let len = pattern.len();
-            let (old_line_1, old_line_temp) = line.split_at(n);
-            let (pattern, old_line_2) = old_line_temp.split_at(len);
-            return format!(
-                "{}**[{}](https://en.wikipedia.org)**{}",
-                old_line_1,
-                pattern,
-                &highlight_pattern(pattern, old_line_2)
-            );
+impl FileStatus {
+    // io errors anywhere in the chain decide the status, `read_to_string`
+    // reports content that is not UTF-8 as `InvalidData`
+    pub fn of_error(e: &Error) -> Self {
+        if e.is::<ChangedSincePreview>() {
+            return FileStatus::Changed;
         }
-        None => return "".to_owned(),
+        let kind = e
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<std::io::Error>())
+            .map(|e| e.kind());
+        match kind {
+            Some(std::io::ErrorKind::PermissionDenied) => FileStatus::PermissionDenied,
+            Some(std::io::ErrorKind::InvalidData) => FileStatus::NotUtf8,
+            _ => FileStatus::Unreadable,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            FileStatus::Unreadable
+                | FileStatus::PermissionDenied
+                | FileStatus::NotUtf8
+                | FileStatus::Changed
+                | FileStatus::NotRenamed
+        )
     }
 }
 
-pub async fn replace_from_vec(
-    find_pat: String,
-    replace: String,
-    paths: Vec<String>,
-) -> Result<String, Error> {
-    let mut output = "".to_owned();
+impl std::fmt::Display for FileStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            FileStatus::Matched => "matched",
+            FileStatus::Replaced => "replaced",
+            FileStatus::NoMatch => "no match",
+            FileStatus::Unreadable => "unreadable",
+            FileStatus::PermissionDenied => "permission denied",
+            FileStatus::NotUtf8 => "not UTF-8",
+            FileStatus::Changed => "changed since preview",
+            FileStatus::Renamed => "renamed",
+            FileStatus::NotRenamed => "not renamed",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+// the outcome for one file, `message` holds the error for failed files
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub status: FileStatus,
+    pub matches: usize,
+    pub message: Option<String>,
+}
+
+impl FileReport {
+    pub(crate) fn failed(path: PathBuf, e: &Error) -> Self {
+        FileReport {
+            path,
+            status: FileStatus::of_error(e),
+            matches: 0,
+            message: Some(format!("{:#}", e)),
+        }
+    }
+
+    pub fn searched(path: PathBuf, result: &Result<Option<FileMatches>, Error>) -> Self {
+        match result {
+            Ok(Some(found)) => FileReport {
+                path,
+                status: FileStatus::Matched,
+                matches: found.lines.iter().map(|line| line.matches.len()).sum(),
+                message: None,
+            },
+            Ok(None) => FileReport {
+                path,
+                status: FileStatus::NoMatch,
+                matches: 0,
+                message: None,
+            },
+            Err(e) => FileReport::failed(path, e),
+        }
+    }
+
+    pub fn replaced(path: PathBuf, result: &Result<usize, Error>) -> Self {
+        match result {
+            Ok(0) => FileReport {
+                path,
+                status: FileStatus::NoMatch,
+                matches: 0,
+                message: None,
+            },
+            Ok(matches) => FileReport {
+                path,
+                status: FileStatus::Replaced,
+                matches: *matches,
+                message: None,
+            },
+            Err(e) => FileReport::failed(path, e),
+        }
+    }
 
-    for path in paths.into_iter() {
-        let result =
-            find_and_replace(find_pat.to_owned(), replace.to_owned(), path.to_string()).await;
+    pub fn renamed(path: PathBuf, result: &Result<(), Error>) -> Self {
         match result {
-            Ok(_) => {
-                output = format!("{}\n- '{}'\n", output, &path);
+            Ok(()) => FileReport {
+                path,
+                status: FileStatus::Renamed,
+                matches: 0,
+                message: None,
+            },
+            Err(e) => FileReport {
+                status: FileStatus::NotRenamed,
+                ..FileReport::failed(path, e)
+            },
+        }
+    }
+
+    // the entry recorded in the history for a file that was written
+    pub fn replaced_file(&self) -> Option<ReplacedFile> {
+        (self.status == FileStatus::Replaced).then(|| ReplacedFile {
+            path: self.path.clone(),
+            matches: self.matches,
+        })
+    }
+}
+
+// returned by `find_and_replace` when the file was edited after the preview
+#[derive(Debug)]
+pub struct ChangedSincePreview(pub PathBuf);
+
+impl std::fmt::Display for ChangedSincePreview {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "'{}' changed since the preview, skipped",
+            self.0.display()
+        )
+    }
+}
+
+impl std::error::Error for ChangedSincePreview {}
+
+// returned by `find_from_vec` when no file contains a match
+#[derive(Debug)]
+pub struct NothingFound;
+
+impl std::fmt::Display for NothingFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Nothing found")
+    }
+}
+
+impl std::error::Error for NothingFound {}
+
+// number of files searched or replaced at the same time
+pub const DEFAULT_WORKERS: usize = 8;
+
+// shared flag stopping a running search or replace, files that are already
+// being processed are finished first so none is left half written
+#[derive(Debug, Clone, Default)]
+pub struct CancelFlag(Arc<AtomicBool>);
+
+impl CancelFlag {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// one search per path, no new one is started once `cancel` is set
+fn find_jobs(
+    rules: Arc<Vec<CompiledRule>>,
+    paths: Vec<PathBuf>,
+    options: SearchOptions,
+    cancel: CancelFlag,
+) -> impl Stream<Item = impl Future<Output = (PathBuf, Result<Option<FileMatches>, Error>)>> {
+    stream::iter(paths)
+        .take_while(move |_| ready(!cancel.is_cancelled()))
+        .map(move |path| {
+            let rules = rules.clone();
+            async move {
+                let result = find(&rules, path.clone(), options).await;
+                (path, result)
             }
-            Err(e) => {
-                eprintln!("Error: {}", e);
+        })
+}
+
+// searches up to `workers` files at a time, yielding every file as soon as it
+// is done, so in no particular order
+pub fn find_stream(
+    rules: Arc<Vec<CompiledRule>>,
+    paths: Vec<PathBuf>,
+    options: SearchOptions,
+    workers: usize,
+    cancel: CancelFlag,
+) -> impl Stream<Item = (PathBuf, Result<Option<FileMatches>, Error>)> {
+    find_jobs(rules, paths, options, cancel).buffer_unordered(workers.max(1))
+}
+
+// like `find_stream`, but keeping the order of `paths`, failed files are
+// reported instead of stopping the search
+pub async fn find_from_vec(
+    rules: Vec<Rule>,
+    paths: Vec<PathBuf>,
+    options: SearchOptions,
+    workers: usize,
+) -> Result<(Vec<FileMatches>, Vec<FileReport>), Error> {
+    let rules = Arc::new(compile_rules(&rules, &options)?);
+    let results: Vec<(PathBuf, Result<Option<FileMatches>, Error>)> =
+        find_jobs(rules, paths, options, CancelFlag::default())
+            .buffered(workers.max(1))
+            .collect()
+            .await;
+    let mut output = vec![];
+    let mut reports = vec![];
+    for (path, result) in results {
+        reports.push(FileReport::searched(path, &result));
+        output.extend(result.ok().flatten());
+    }
+
+    // files that could not be read may still hold a match
+    if output.is_empty() && !reports.iter().any(|report| report.status.is_error()) {
+        bail!(NothingFound);
+    } else {
+        Ok((output, reports))
+    }
+}
+
+// a function that will find all the occurrences of the pattern in a file (path)
+pub async fn find(
+    rules: &[CompiledRule],
+    path: PathBuf,
+    options: SearchOptions,
+) -> Result<Option<FileMatches>, Error> {
+    let reader = tokio::fs::read_to_string(&path)
+        .await
+        .with_context(|| format!("Could not read file: '{}'", path.display()))?;
+    let (bom, content) = strip_bom(&reader);
+    let index = LineIndex::new(content);
+    let mut lines: Vec<MatchedLine> = vec![];
+
+    for m in match_rules(rules, content, &options) {
+        let line = index.line_of(m.range.start);
+        let end_line = if m.range.is_empty() {
+            line
+        } else {
+            index.line_of(m.range.end - 1)
+        };
+        let found = Match {
+            path: path.clone(),
+            rule: m.rule,
+            line,
+            end_line,
+            column: content[index.range(line).start..m.range.start]
+                .chars()
+                .count()
+                + 1,
+            byte_range: bom.len() + m.range.start..bom.len() + m.range.end,
+            before: content[m.range].to_owned(),
+            after: m.after,
+        };
+
+        // matches sharing a line are shown together
+        match lines.last_mut() {
+            Some(block) if line <= block.end_line => {
+                block.end_line = block.end_line.max(end_line);
+                block.matches.push(found);
+            }
+            _ => lines.push(MatchedLine {
+                line,
+                end_line,
+                byte_offset: 0,
+                text: "".to_owned(),
+                matches: vec![found],
+            }),
+        }
+    }
+
+    for block in lines.iter_mut() {
+        let start = index.range(block.line).start;
+        // a match may also swallow the line ending of its last line
+        let end = block
+            .matches
+            .iter()
+            .map(|m| m.byte_range.end - bom.len())
+            .fold(index.range(block.end_line).end, usize::max);
+        block.byte_offset = bom.len() + start;
+        block.text = content[start..end].to_owned();
+    }
+
+    if lines.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(FileMatches {
+        path,
+        lines,
+        hash: content_hash(reader.as_bytes()),
+    }))
+}
+
+// one replace per target backing up into its own fork of `backup`, no new one
+// is started once `cancel` is set
+fn replace_jobs(
+    rules: Arc<Vec<CompiledRule>>,
+    targets: Vec<ReplaceTarget>,
+    options: SearchOptions,
+    backup: &Backup,
+    cancel: CancelFlag,
+) -> impl Stream<Item = impl Future<Output = (PathBuf, Backup, Result<usize, Error>)>> {
+    let backup = backup.fork();
+    stream::iter(targets)
+        .take_while(move |_| ready(!cancel.is_cancelled()))
+        .map(move |target| {
+            let (rules, mut part) = (rules.clone(), backup.fork());
+            async move {
+                let path = target.path.clone();
+                let result = find_and_replace(&rules, target, options, &mut part).await;
+                (path, part, result)
             }
+        })
+}
+
+// replaces in up to `workers` files at a time, yielding every file with the
+// part of the backup holding it as soon as it is done
+pub fn replace_stream(
+    rules: Arc<Vec<CompiledRule>>,
+    targets: Vec<ReplaceTarget>,
+    options: SearchOptions,
+    workers: usize,
+    backup: &Backup,
+    cancel: CancelFlag,
+) -> impl Stream<Item = (PathBuf, Backup, Result<usize, Error>)> {
+    replace_jobs(rules, targets, options, backup, cancel).buffer_unordered(workers.max(1))
+}
+
+// like `replace_stream`, merging the parts of the backup into `backup` in the
+// order of `targets`, failed files are reported instead of stopping the replace
+pub async fn replace_from_vec(
+    rules: Vec<Rule>,
+    targets: Vec<ReplaceTarget>,
+    options: SearchOptions,
+    workers: usize,
+    mut backup: Backup,
+) -> Result<(String, Backup, Vec<FileReport>), Error> {
+    let rules = Arc::new(compile_rules(&rules, &options)?);
+    let mut output = "".to_owned();
+    let mut reports = vec![];
+
+    let results: Vec<(PathBuf, Backup, Result<usize, Error>)> =
+        replace_jobs(rules, targets, options, &backup, CancelFlag::default())
+            .buffered(workers.max(1))
+            .collect()
+            .await;
+
+    for (path, part, result) in results {
+        backup.merge(part);
+        let report = FileReport::replaced(path, &result);
+        if report.status == FileStatus::Replaced {
+            output = format!("{}\n- '{}'\n", output, report.path.display());
         }
+        reports.push(report);
     }
 
-    Ok(output)
+    Ok((output, backup, reports))
 }
 
+// applies every rule to the file in a single pass and returns the number of
+// replaced matches
 pub async fn find_and_replace(
-    find: String,
-    replace_with: String,
-    path: String,
-) -> Result<(), Error> {
-    let _f = fs::File::open(&path)?;
+    rules: &[CompiledRule],
+    target: ReplaceTarget,
+    options: SearchOptions,
+    backup: &mut Backup,
+) -> Result<usize, Error> {
+    let ReplaceTarget { path, lines, hash } = target;
+    let _f = fs::File::open(&path)
+        .with_context(|| format!("Could not open file: '{}'", path.display()))?;
     let reader = tokio::fs::read_to_string(&path)
         .await
-        .with_context(|| format!("Could not read file: '{}'", &path))?;
-    let mut text = "".to_string();
-    let mut file_contains_pattern = false;
+        .with_context(|| format!("Could not read file: '{}'", path.display()))?;
+    // the selected lines only make sense for the content that was previewed
+    if hash.is_some_and(|hash| hash != content_hash(reader.as_bytes())) {
+        bail!(ChangedSincePreview(path));
+    }
+    let matches = selected_matches(rules, &reader, lines.as_ref(), &options);
 
-    for (_num, line) in reader.lines().enumerate() {
-        let unwraped_line = format!("{}", line);
+    // leave files without a match untouched, so their mtime is not bumped
+    if matches.is_empty() {
+        return Ok(0);
+    }
+    let text = apply_matches(&reader, &matches);
 
-        if unwraped_line.contains(&find) {
-            file_contains_pattern = true;
-            let new_line = unwraped_line.replace(&find, &replace_with);
-            text = format!("{}{}\n", &text, &new_line);
-        } else {
-            text = format!("{}{}\n", &text, &unwraped_line);
-        }
+    // keep the original content so the operation can be undone
+    backup.save(&path, &reader, &text).await?;
+
+    if let Err(e) = write_atomic(&path, text.as_bytes()).await {
+        backup.discard(&path).await;
+        return Err(e).with_context(|| format!("Error writing to file '{}'!", path.display()));
+    }
+
+    Ok(matches.len())
+}
+
+// writes to a temporary sibling file which is synced and then renamed over the
+// original, so the file is either fully old or fully new even after a crash
+pub async fn write_atomic(path: &Path, content: &[u8]) -> Result<(), Error> {
+    // write through symlinks instead of replacing them with a regular file
+    let path = tokio::fs::canonicalize(path).await?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let name = path
+        .file_name()
+        .with_context(|| format!("Not a file: {:?}", path))?;
+    let temp = dir.join(format!(
+        ".{}.{}.frr-tmp",
+        name.to_string_lossy(),
+        Uuid::new_v4()
+    ));
+    let permissions = tokio::fs::metadata(&path).await?.permissions();
+    // renaming over the file only needs write access to the directory, so a
+    // read-only file would be replaced anyway
+    if permissions.readonly() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!("'{}' is read-only", path.display()),
+        )
+        .into());
+    }
+
+    let result = async {
+        let mut file = tokio::fs::File::create(&temp).await?;
+        file.write_all(content).await?;
+        file.sync_all().await?;
+        tokio::fs::set_permissions(&temp, permissions).await?;
+        tokio::fs::rename(&temp, &path).await
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&temp).await;
+    }
+    result?;
+
+    // persist the rename itself, not supported for directories on every platform
+    if let Ok(dir) = tokio::fs::File::open(dir).await {
+        let _ = dir.sync_all().await;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        build_pattern, compile_rules, find, find_and_replace, split_lines, strip_bom, FileMatches,
+        FileStatus, ReplaceTarget, Rule, SearchOptions,
+    };
+    use crate::backup::Backup;
+    use anyhow::Error;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    fn create_file_with_contents(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    // compiles the rules for a single file, the streams compile them once for all files
+    async fn find_in(
+        rules: Vec<Rule>,
+        path: PathBuf,
+        options: SearchOptions,
+    ) -> Result<Option<FileMatches>, Error> {
+        find(&compile_rules(&rules, &options)?, path, options).await
+    }
+
+    async fn replace_in(
+        rules: Vec<Rule>,
+        target: ReplaceTarget,
+        options: SearchOptions,
+        backup: &mut Backup,
+    ) -> Result<usize, Error> {
+        find_and_replace(&compile_rules(&rules, &options)?, target, options, backup).await
+    }
+
+    fn bury_in_lorem_ipsum(contents: &str) -> String {
+        format!("{}{}{}", lipsum::lipsum(100), contents, lipsum::lipsum(100))
+    }
+
+    const RUN: &str = "241220_LU02_tzajec_RNase_modifications_E0-0_01";
+    const NEW_RUN: &str = "241220_LU02_tzajec_RNase_modifications_E0-0_02";
+
+    #[tokio::test]
+    async fn find_and_replace_works_basic() {
+        let dir = tempfile::tempdir().unwrap();
+        let contents = bury_in_lorem_ipsum(&format!("{}/{}.c.mzXML", RUN, RUN));
+        let path = create_file_with_contents(dir.path(), "parameters.txt", &contents);
+
+        let result = replace_in(
+            vec![Rule::new(RUN, NEW_RUN)],
+            ReplaceTarget::from(path.clone()),
+            SearchOptions::default(),
+            &mut Backup::in_dir(dir.path()),
+        )
+        .await;
+
+        assert_eq!(result.unwrap(), 2);
+        let new_contents = fs::read_to_string(&path).unwrap();
+        assert!(new_contents.contains(&format!("{}/{}.c.mzXML", NEW_RUN, NEW_RUN)));
+        assert!(!new_contents.contains(RUN));
     }
 
-    let _ = tokio::fs::write(path.clone(), text)
+    #[tokio::test]
+    async fn find_works_basic() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = create_file_with_contents(dir.path(), "text.txt", "nothing\nsay izak\n");
+
+        let result = find_in(
+            vec![Rule::new("izak", "tina")],
+            path.clone(),
+            SearchOptions::default(),
+        )
         .await
-        .with_context(|| format!("Error writing to file '{}'!", path));
+        .unwrap()
+        .unwrap();
 
-    if file_contains_pattern {
-        Ok(())
-    } else {
-        Err(Error::new(io::Error::new(
-            io::ErrorKind::NotFound,
-            "patter not found in file",
-        )))
-    }
-}
-
-// mod tests {
-//     use super::{find, find_and_replace};
-//     use std::{
-//         fs::{self, File},
-//         io::{self, Read},
-//     };
-
-//     fn create_file_with_contents(path: &str, contents: &str) -> Result<String, io::Error> {
-//         let _new_file = fs::File::create(path).unwrap();
-//         fs::write(path, contents).unwrap();
-//         Ok(path.to_owned())
-//     }
-
-//     fn bury_in_lorem_ipsum(contents: &str) -> String {
-//         format!("{}{}{}", lipsum::lipsum(100), contents, lipsum::lipsum(100))
-//     }
-
-//     #[test]
-//     fn find_and_replace_works_basic() {
-//         let path = ".test.txt";
-//         let find = "241220_LU02_tzajec_RNase_modifications_E0-0_01/241220_LU02_tzajec_RNase_modifications_E0-0_01.c.mzXML";
-//         let contents = bury_in_lorem_ipsum(find);
-//         let replace = "241220_LU02_tzajec_RNase_modifications_E0-0_02/241220_LU02_tzajec_RNase_modifications_E0-0_01.c.mzXML";
-//         let _ = create_file_with_contents(path, &contents).unwrap();
-//         let result = find_and_replace(find.to_owned(), replace.to_owned(), path.to_owned()).await;
-
-//         assert!(result.is_ok());
-
-//         // check if you can find the replaced pattern in the file
-//         let new_contents = fs::read_to_string(path).unwrap();
-
-//         assert!(new_contents.contains(replace));
-
-//         let remove = fs::remove_file(path);
-//         assert!(remove.is_ok())
-//     }
-
-//     #[test]
-//     fn find_works_basic() {
-//         let _ = create_file_with_contents(".text.txt", "izak");
-//         let contents = fs::read_to_string(".text.txt").unwrap();
-//         let result = find("izak".to_owned(), "tina".to_owned(), &contents);
-//         assert!(result.is_ok());
-//         assert_eq!(
-//             result.unwrap(),
-//             "File: '.text.txt'\n\n\n1: izak\n   ^^^^\n=> tina\n   ^^^^"
-//         );
-
-//         let remove = fs::remove_file(".text.txt");
-//         assert!(remove.is_ok())
-//     }
-
-//     #[test]
-//     fn find_and_replace_works_with_sample_data() {
-//         let path = "/home/izak/dev/tina/Rust/frr/data/test_files/241220_LU02_tzajec_RNase_modifications_E0-0_01/Search_1/parameters_search_1.txt";
-//         let result = find_and_replace(
-//             "241220_LU02_tzajec_RNase_modifications_E0-0_01",
-//             "241220_LU02_tzajec_RNase_modifications_E0-0_012345",
-//             path,
-//         );
-//         let mut file = fs::File::open(path).unwrap();
-//         let mut new_contents = "".to_owned();
-//         let read_result = file.read_to_string(&mut new_contents);
-//         assert!(result.is_ok());
-//         assert!(read_result.is_ok());
-//         assert!(result.is_ok());
-
-//         assert!(new_contents.contains("241220_LU02_tzajec_RNase_modifications_E0-0_012345"));
-
-//         let _ = find_and_replace(
-//             "241220_LU02_tzajec_RNase_modifications_E0-0_012345",
-//             "241220_LU02_tzajec_RNase_modifications_E0-0_01",
-//             path,
-//         );
-
-//         assert!(new_contents.contains("241220_LU02_tzajec_RNase_modifications_E0-0_01"));
-//     }
-
-//     #[test]
-//     fn find_finds_the_pattern() {
-//         let path = "/home/izak/dev/tina/Rust/frr/data/test_files/241220_LU02_tzajec_RNase_modifications_E0-0_01/Search_2/parameters_search_2.txt";
-//         let result = find(
-//             "241220_LU02_tzajec_RNase_modifications_E0-0_01",
-//             "241220_LU02_tzajec_RNase_modifications_E0-0_012345",
-//             path,
-//         );
-//         assert!(result.is_ok());
-//         assert!(result.unwrap().contains("^^^^"));
-//     }
-// }
+        assert_eq!(result.path, path);
+        assert_eq!(result.lines.len(), 1);
+        let m = &result.lines[0].matches[0];
+        assert_eq!((m.line, m.column), (2, 5));
+        assert_eq!(m.byte_range, 12..16);
+        assert_eq!((m.before.as_str(), m.after.as_str()), ("izak", "tina"));
+    }
+
+    #[tokio::test]
+    async fn find_without_match_is_none() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = create_file_with_contents(dir.path(), "text.txt", "nothing here");
+
+        let result = find_in(
+            vec![Rule::new("izak", "tina")],
+            path,
+            SearchOptions::default(),
+        )
+        .await;
+
+        assert!(result.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn find_and_replace_only_touches_selected_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = create_file_with_contents(dir.path(), "text.txt", "izak\nizak\nizak\n");
+
+        replace_in(
+            vec![Rule::new("izak", "tina")],
+            ReplaceTarget {
+                path: path.clone(),
+                lines: Some(vec![1, 3]),
+                hash: None,
+            },
+            SearchOptions::default(),
+            &mut Backup::in_dir(dir.path()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "tina\nizak\ntina\n");
+    }
+
+    #[tokio::test]
+    async fn find_and_replace_expands_capture_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = create_file_with_contents(dir.path(), "text.txt", "E0-0_01 E0-0_$1");
+
+        replace_in(
+            vec![Rule::new(r"E0-0_(\d+)", "E0-1_${1}")],
+            ReplaceTarget::from(path.clone()),
+            SearchOptions {
+                regex: true,
+                ..SearchOptions::default()
+            },
+            &mut Backup::in_dir(dir.path()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "E0-1_01 E0-0_$1");
+
+        // in plain mode neither the pattern nor the replacement is special
+        replace_in(
+            vec![Rule::new("E0-0_$1", "$0")],
+            ReplaceTarget::from(path.clone()),
+            SearchOptions::default(),
+            &mut Backup::in_dir(dir.path()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "E0-1_01 $0");
+    }
+
+    #[tokio::test]
+    async fn find_and_replace_keeps_line_endings_and_bom() {
+        let dir = tempfile::tempdir().unwrap();
+        let contents = "\u{feff}izak\r\nrest\r\n\nizak";
+        let path = create_file_with_contents(dir.path(), "text.txt", contents);
+
+        replace_in(
+            vec![Rule::new("izak", "tina")],
+            ReplaceTarget::from(path.clone()),
+            SearchOptions::default(),
+            &mut Backup::in_dir(dir.path()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "\u{feff}tina\r\nrest\r\n\ntina"
+        );
+    }
+
+    #[tokio::test]
+    async fn find_and_replace_leaves_unmatched_files_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = create_file_with_contents(dir.path(), "text.txt", "nothing here");
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let mut backup = Backup::in_dir(dir.path());
+
+        let result = replace_in(
+            vec![Rule::new("izak", "tina")],
+            ReplaceTarget::from(path.clone()),
+            SearchOptions::default(),
+            &mut backup,
+        )
+        .await;
+
+        assert_eq!(result.unwrap(), 0);
+        assert!(backup.is_empty());
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+    }
+
+    #[tokio::test]
+    async fn find_and_replace_matches_across_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let contents = "header\r\n[run]\r\nname=E0-0_01\r\nend\r\n";
+        let path = create_file_with_contents(dir.path(), "search.params", contents);
+        let options = SearchOptions {
+            multiline: true,
+            ..SearchOptions::default()
+        };
+
+        let result = find_in(
+            vec![Rule::new(
+                "[run]\\nname=E0-0_01",
+                "[run]\\nname=E0-1_01\\nslot=2",
+            )],
+            path.clone(),
+            options,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let block = &result.lines[0];
+        assert_eq!((block.line, block.end_line), (2, 3));
+        assert_eq!(block.text, "[run]\r\nname=E0-0_01");
+        assert_eq!(block.matches[0].after, "[run]\r\nname=E0-1_01\r\nslot=2");
+
+        replace_in(
+            vec![Rule::new(
+                "[run]\\nname=E0-0_01",
+                "[run]\\nname=E0-1_01\\nslot=2",
+            )],
+            ReplaceTarget {
+                path: path.clone(),
+                lines: Some(vec![2, 3]),
+                hash: None,
+            },
+            options,
+            &mut Backup::in_dir(dir.path()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "header\r\n[run]\r\nname=E0-1_01\r\nslot=2\r\nend\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn find_and_replace_applies_rules_without_cascading() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = create_file_with_contents(dir.path(), "text.txt", "a b ab\nc\n");
+
+        let result = find_in(
+            vec![
+                Rule::new("ab", "x"),
+                Rule::new("a", "b"),
+                Rule::new("b", "c"),
+            ],
+            path.clone(),
+            SearchOptions::default(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let rules: Vec<usize> = result.matches().map(|m| m.rule).collect();
+        assert_eq!(rules, vec![1, 2, 0]);
+
+        replace_in(
+            vec![
+                Rule::new("ab", "x"),
+                Rule::new("a", "b"),
+                Rule::new("b", "c"),
+            ],
+            ReplaceTarget::from(path.clone()),
+            SearchOptions::default(),
+            &mut Backup::in_dir(dir.path()),
+        )
+        .await
+        .unwrap();
+
+        // the `b` produced by the second rule is not replaced again by the third
+        assert_eq!(fs::read_to_string(&path).unwrap(), "b c x\nc\n");
+    }
+
+    #[tokio::test]
+    async fn find_and_replace_skips_files_changed_since_the_preview() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = create_file_with_contents(dir.path(), "text.txt", "izak\nizak\n");
+        let rules = vec![Rule::new("izak", "tina")];
+
+        let found = find_in(rules.clone(), path.clone(), SearchOptions::default())
+            .await
+            .unwrap()
+            .unwrap();
+        let target = ReplaceTarget {
+            path: path.clone(),
+            lines: Some(vec![2]),
+            hash: Some(found.hash),
+        };
+        // the first line is removed, so line 2 now holds something else
+        fs::write(&path, "izak\n").unwrap();
+
+        let mut backup = Backup::in_dir(dir.path());
+        let result = replace_in(rules, target, SearchOptions::default(), &mut backup).await;
+
+        assert_eq!(
+            FileStatus::of_error(&result.unwrap_err()),
+            FileStatus::Changed
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "izak\n");
+        assert!(backup.files.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_writes_are_not_kept_in_the_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        // a valid name, but too long for the temporary file written next to it
+        let path = create_file_with_contents(dir.path(), &"a".repeat(240), "izak\n");
+
+        let mut backup = Backup::in_dir(dir.path());
+        let result = replace_in(
+            vec![Rule::new("izak", "tina")],
+            ReplaceTarget::from(path.clone()),
+            SearchOptions::default(),
+            &mut backup,
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "izak\n");
+        assert!(backup.files.is_empty());
+        assert!(backup.restore().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn read_only_files_are_not_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = create_file_with_contents(dir.path(), "text.txt", "izak\n");
+        let mut permissions = fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&path, permissions).unwrap();
+
+        let mut backup = Backup::in_dir(dir.path());
+        let result = replace_in(
+            vec![Rule::new("izak", "tina")],
+            ReplaceTarget::from(path.clone()),
+            SearchOptions::default(),
+            &mut backup,
+        )
+        .await;
+
+        assert_eq!(
+            FileStatus::of_error(&result.unwrap_err()),
+            FileStatus::PermissionDenied
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "izak\n");
+        assert!(backup.files.is_empty());
+    }
+
+    #[tokio::test]
+    async fn regex_anchors_still_match_per_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = create_file_with_contents(dir.path(), "text.txt", "a1\r\na2\r\nb3");
+
+        replace_in(
+            vec![Rule::new(r"^a(\d)$", "c$1")],
+            ReplaceTarget::from(path.clone()),
+            SearchOptions {
+                regex: true,
+                ..SearchOptions::default()
+            },
+            &mut Backup::in_dir(dir.path()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "c1\r\nc2\r\nb3");
+    }
+
+    #[tokio::test]
+    async fn regex_anchors_do_not_add_a_line_after_the_final_newline() {
+        let dir = tempfile::tempdir().unwrap();
+        let cases = [
+            ("^", "# ", "a1\na2\n", "# a1\n# a2\n"),
+            ("$", ";", "a1\na2\n", "a1;\na2;\n"),
+            ("$", ";", "a1\r\na2\r\n", "a1;\r\na2;\r\n"),
+            ("$", ";", "a1\na2", "a1;\na2;"),
+        ];
+
+        for (find, replace, contents, expected) in cases {
+            let path = create_file_with_contents(dir.path(), "text.txt", contents);
+            replace_in(
+                vec![Rule::new(find, replace)],
+                ReplaceTarget::from(path.clone()),
+                SearchOptions {
+                    regex: true,
+                    ..SearchOptions::default()
+                },
+                &mut Backup::in_dir(dir.path()),
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn build_pattern_honours_case_and_word_options() {
+        let line = "aaaaaizakaaaa and Izak -01";
+        let options = SearchOptions::default();
+        assert_eq!(
+            build_pattern("izak", &options)
+                .unwrap()
+                .find_iter(line)
+                .count(),
+            1
+        );
+
+        let options = SearchOptions {
+            case_insensitive: true,
+            ..SearchOptions::default()
+        };
+        assert_eq!(
+            build_pattern("izak", &options)
+                .unwrap()
+                .find_iter(line)
+                .count(),
+            2
+        );
+
+        let options = SearchOptions {
+            case_insensitive: true,
+            whole_word: true,
+            ..SearchOptions::default()
+        };
+        let pattern = build_pattern("izak", &options).unwrap();
+        assert_eq!(pattern.find(line).unwrap().as_str(), "Izak");
+        assert!(build_pattern("-01", &options).unwrap().is_match(line));
+    }
+
+    #[test]
+    fn split_lines_keeps_every_byte() {
+        let (bom, content) = strip_bom("\u{feff}a\r\nb\n\nc");
+        let lines: Vec<(&str, &str)> = split_lines(content).collect();
+
+        assert_eq!(bom, "\u{feff}");
+        assert_eq!(
+            lines,
+            vec![("a", "\r\n"), ("b", "\n"), ("", "\n"), ("c", "")]
+        );
+    }
+}
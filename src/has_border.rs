@@ -9,7 +9,7 @@ pub trait HasBorder {
 
 impl HasBorder for button::Style {
     fn set_border_radius(&mut self, radius: f32) -> button::Style {
-        let mut clone = self.clone();
+        let mut clone = *self;
         clone.border.radius = border::radius(radius);
         clone
     }
@@ -17,7 +17,7 @@ impl HasBorder for button::Style {
 
 impl HasBorder for text_input::Style {
     fn set_border_radius(&mut self, radius: f32) -> text_input::Style {
-        let mut clone = self.clone();
+        let mut clone = *self;
         clone.border.radius = border::radius(radius);
         clone
     }
@@ -25,7 +25,7 @@ impl HasBorder for text_input::Style {
 
 impl HasBorder for container::Style {
     fn set_border_radius(&mut self, radius: f32) -> container::Style {
-        let mut clone = self.clone();
+        let mut clone = *self;
         clone.border.radius = border::radius(radius);
         clone
     }
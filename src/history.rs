@@ -0,0 +1,135 @@
+use anyhow::{Context, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::find_and_replace::{serialize_path, ReplacedFile, Rule};
+use crate::preset::config_dir;
+use crate::rename::Rename;
+
+// a single replace operation as recorded in the history journal
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    // seconds since the unix epoch
+    pub timestamp: u64,
+    pub id: Uuid,
+    #[serde(serialize_with = "serialize_path")]
+    pub dir: PathBuf,
+    pub rules: Vec<Rule>,
+    pub files: Vec<ReplacedFile>,
+    #[serde(default)]
+    pub renames: Vec<Rename>,
+}
+
+impl HistoryEntry {
+    pub fn new(
+        dir: &Path,
+        rules: Vec<Rule>,
+        files: Vec<ReplacedFile>,
+        renames: Vec<Rename>,
+    ) -> Self {
+        HistoryEntry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs())
+                .unwrap_or_default(),
+            id: Uuid::new_v4(),
+            dir: dir.to_owned(),
+            rules,
+            files,
+            renames,
+        }
+    }
+
+    pub fn matches(&self) -> usize {
+        self.files.iter().map(|file| file.matches).sum()
+    }
+}
+
+// `~/.config/frr/history.jsonl`
+pub fn history_path() -> Result<PathBuf, Error> {
+    Ok(config_dir()?.join("history.jsonl"))
+}
+
+// the journal is append-only with one JSON object per line, so an interrupted
+// write can at most damage the last entry
+pub async fn append_entry(path: PathBuf, entry: HistoryEntry) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Could not create config directory {:?}", dir))?;
+    }
+    let mut line = serde_json::to_string(&entry)?;
+    line.push('\n');
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .await
+        .with_context(|| format!("Could not open history {:?}", path))?;
+    file.write_all(line.as_bytes())
+        .await
+        .with_context(|| format!("Could not write history {:?}", path))?;
+    file.sync_all().await?;
+    Ok(())
+}
+
+// oldest entry first, lines that can not be parsed are skipped
+pub async fn load_history(path: PathBuf) -> Result<Vec<HistoryEntry>, Error> {
+    if !Path::new(&path).exists() {
+        return Ok(vec![]);
+    }
+    let content = tokio::fs::read_to_string(&path)
+        .await
+        .with_context(|| format!("Could not read history {:?}", path))?;
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{append_entry, load_history, HistoryEntry};
+    use crate::find_and_replace::{ReplacedFile, Rule};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    #[tokio::test]
+    async fn history_is_appended_and_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("frr").join("history.jsonl");
+        assert!(load_history(path.clone()).await.unwrap().is_empty());
+
+        let first = HistoryEntry::new(
+            Path::new("/data"),
+            vec![Rule::new("izak", "tina")],
+            vec![ReplacedFile {
+                path: PathBuf::from("/data/a.txt"),
+                matches: 2,
+            }],
+            vec![],
+        );
+        let second = HistoryEntry::new(
+            Path::new("/data"),
+            vec![Rule::new("tina", "izak")],
+            vec![],
+            vec![],
+        );
+        append_entry(path.clone(), first.clone()).await.unwrap();
+        // a damaged line does not hide the other entries
+        fs::write(
+            &path,
+            format!("{}{{\"broken\n", fs::read_to_string(&path).unwrap()),
+        )
+        .unwrap();
+        append_entry(path.clone(), second.clone()).await.unwrap();
+
+        let history = load_history(path).await.unwrap();
+        assert_eq!(history, vec![first, second]);
+        assert_eq!(history[0].matches(), 2);
+    }
+}
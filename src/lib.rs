@@ -0,0 +1,9 @@
+pub mod backup;
+pub mod cli;
+pub mod diff;
+pub mod dir_crawl;
+pub mod find_and_replace;
+pub mod history;
+pub mod presenter;
+pub mod preset;
+pub mod rename;
@@ -1,28 +1,197 @@
-use find_and_replace::{find_from_vec, replace_from_vec};
+use anyhow::Error;
+use clap::Parser;
+use frr::find_and_replace::{
+    compile_rules, find_stream, replace_stream, CancelFlag, CompiledRule, FileMatches, FileReport,
+    FileStatus, ReplaceTarget, Rule, SearchOptions, DEFAULT_WORKERS,
+};
 use iced::widget::button::Status;
 use iced::widget::markdown::Url;
 use iced::widget::scrollable::{scroll_by, AbsoluteOffset, Id};
-use iced::widget::{button, column, container, markdown, row, scrollable, text_input, Container};
-use iced::{keyboard, Size, Task, Theme};
+use iced::widget::{
+    button, checkbox, column, container, markdown, pick_list, row, scrollable, text, text_input,
+    Column, Container,
+};
+use iced::{keyboard, Element, Padding, Size, Task, Theme};
 use rfd::AsyncFileDialog;
 
-mod dir_crawl;
-mod find_and_replace;
 mod has_border;
 
-use dir_crawl::dir_crawl;
+use frr::backup::Backup;
+use frr::cli::{self, Args};
+use frr::diff::diff_from_vec;
+use frr::dir_crawl::{dir_crawl_async, parse_dir_list, CrawlOptions, FileFilter, DEFAULT_FILTER};
+use frr::history::{self, HistoryEntry};
+use frr::presenter;
+use frr::preset::{self, Preset};
+use frr::rename::{find_renames, rename_all, Rename};
 use has_border::HasBorder;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 #[derive(Clone)]
 struct State {
     find: (String, String),
     replace: (String, String),
-    path: String,
+    // extra rules applied after the first find/replace pair
+    rules: Vec<Rule>,
+    path: PathBuf,
+    filter: String,
+    crawl: CrawlOptions,
+    // excluded directory names as typed, parsed into `crawl`
+    exclude_dirs: String,
     text: String,
     markdown: Vec<markdown::Item>,
     confirm: bool,
-    file_list: Vec<String>,
+    file_list: Vec<PathBuf>,
     focus: String,
+    options: SearchOptions,
+    // number of files searched or replaced at the same time
+    workers: usize,
+    last_backup: Option<Backup>,
+    // where the originals of replaced files are backed up
+    backup_dir: PathBuf,
+    preview: Vec<PreviewFile>,
+    previewed: Option<Previewed>,
+    // also rename matching file and directory names below the path
+    rename: bool,
+    renames: Vec<PreviewRename>,
+    // unified diff of the selected changes, shown instead of the checkboxes
+    diff: Option<Vec<markdown::Item>>,
+    // set while a search or replace runs, cancelling it stops starting new files
+    running: Option<CancelFlag>,
+    progress: Progress,
+    // what happened to every file of the last search or replace
+    report: Vec<FileReport>,
+    // entries the last crawl could not read
+    skipped: usize,
+    replacing: Option<ReplaceRun>,
+    preset_name: String,
+    presets: Vec<Preset>,
+    // where presets are saved, None when the home directory is unknown
+    presets_path: Option<PathBuf>,
+    // past replace operations, loaded when the history is opened
+    history: Vec<HistoryEntry>,
+    history_path: Option<PathBuf>,
+    show_history: bool,
+}
+
+// a file of the preview whose matched lines can be excluded from the replace
+#[derive(Clone)]
+struct PreviewFile {
+    path: PathBuf,
+    selected: bool,
+    matches: usize,
+    // content hash at the time of the search
+    hash: u64,
+    lines: Vec<PreviewLine>,
+}
+
+#[derive(Clone)]
+struct PreviewLine {
+    line_num: usize,
+    end_line: usize,
+    selected: bool,
+    markdown: Vec<markdown::Item>,
+}
+
+// files handled so far by the running search or replace
+#[derive(Clone, Default)]
+struct Progress {
+    total: usize,
+    done: usize,
+    matches: usize,
+    current: String,
+}
+
+impl Progress {
+    fn new(total: usize) -> Self {
+        Progress {
+            total,
+            ..Progress::default()
+        }
+    }
+
+    fn update(&mut self, path: &Path, matches: usize) {
+        self.done += 1;
+        self.matches += matches;
+        self.current = path.display().to_string();
+    }
+
+    fn markdown(&self, heading: &str) -> String {
+        format!(
+            "# {}\n\n{}/{} files, {} matches so far, last file: '{}'",
+            heading, self.done, self.total, self.matches, self.current
+        )
+    }
+}
+
+// the rules, options and directory a preview was made with, the replace
+// applies exactly these even if the inputs were edited in the meantime
+#[derive(Debug, Clone)]
+struct Previewed {
+    rules: Vec<Rule>,
+    compiled: Arc<Vec<CompiledRule>>,
+    options: SearchOptions,
+    root: PathBuf,
+}
+
+// what a running replace did so far, needed for the undo and the history
+#[derive(Clone)]
+struct ReplaceRun {
+    rules: Vec<Rule>,
+    root: PathBuf,
+    backup: Backup,
+    renames: Vec<Rename>,
+}
+
+// a rename of the preview, renames with a conflict can not be selected
+#[derive(Clone)]
+struct PreviewRename {
+    rename: Rename,
+    selected: bool,
+}
+
+impl From<FileMatches> for PreviewFile {
+    fn from(file: FileMatches) -> Self {
+        PreviewFile {
+            matches: file.matches().count(),
+            path: file.path,
+            selected: true,
+            hash: file.hash,
+            lines: file
+                .lines
+                .into_iter()
+                .map(|line| PreviewLine {
+                    line_num: line.line,
+                    end_line: line.end_line,
+                    selected: true,
+                    markdown: markdown::parse(&presenter::markdown_line(&line)).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl PreviewFile {
+    // files without any selected line are skipped entirely
+    fn replace_target(&self) -> Option<ReplaceTarget> {
+        let lines: Vec<usize> = self
+            .lines
+            .iter()
+            .filter(|line| line.selected)
+            .flat_map(|line| line.line_num..=line.end_line)
+            .collect();
+
+        if lines.is_empty() {
+            return None;
+        }
+
+        Some(ReplaceTarget {
+            path: self.path.clone(),
+            lines: Some(lines),
+            hash: Some(self.hash),
+        })
+    }
 }
 
 impl Default for State {
@@ -30,35 +199,154 @@ impl Default for State {
         State {
             find: ("".to_owned(), "".to_owned()),
             replace: ("".to_owned(), "".to_owned()),
-            path: "".to_owned(),
+            rules: vec![],
+            path: PathBuf::new(),
+            filter: DEFAULT_FILTER.to_owned(),
+            crawl: CrawlOptions::default(),
+            exclude_dirs: "".to_owned(),
             text: "".to_owned(),
             markdown: markdown::parse("").collect(),
             confirm: false,
-            file_list: vec!["".to_owned()],
+            file_list: vec![],
             focus: "find".to_owned(),
+            options: SearchOptions::default(),
+            workers: DEFAULT_WORKERS,
+            last_backup: None,
+            backup_dir: std::env::temp_dir(),
+            preview: vec![],
+            previewed: None,
+            rename: false,
+            renames: vec![],
+            diff: None,
+            running: None,
+            progress: Progress::default(),
+            report: vec![],
+            skipped: 0,
+            replacing: None,
+            preset_name: "".to_owned(),
+            presets: vec![],
+            presets_path: None,
+            history: vec![],
+            history_path: None,
+            show_history: false,
         }
     }
 }
 
 impl State {
+    // the inputs are locked while a preview waits for confirmation and while a
+    // search or replace runs
+    fn editable(&self) -> bool {
+        !self.confirm && self.running.is_none()
+    }
+
+    // the changes still selected in the preview
+    fn replace_targets(&self) -> Vec<ReplaceTarget> {
+        self.preview
+            .iter()
+            .filter_map(|file| file.replace_target())
+            .collect()
+    }
+
+    // the default state with the presets and history from the user's config directory
+    fn with_config() -> Self {
+        let mut state = State {
+            history_path: history::history_path().ok(),
+            ..State::default()
+        };
+        match preset::config_path() {
+            Ok(path) => {
+                match preset::load_presets(&path) {
+                    Ok(presets) => state.presets = presets,
+                    Err(e) => state.text = format!("{:#}", e),
+                }
+                state.presets_path = Some(path);
+            }
+            Err(e) => state.text = format!("{}", e),
+        }
+        state.update_markdown();
+        state
+    }
+
     pub fn update_markdown(&mut self) {
         self.markdown = markdown::parse(&self.text).collect();
     }
+
+    // the directory input, a relative path is resolved against the working
+    // directory, canonical like the crawled files so renames and backups agree
+    fn root(&self) -> Result<PathBuf, String> {
+        std::fs::canonicalize(&self.path)
+            .map_err(|e| format!("Invalid directory '{}': {}", self.path.display(), e))
+    }
+
+    // the find/replace inputs followed by every extra rule, fully empty rows are skipped
+    fn all_rules(&self) -> Result<Vec<Rule>, String> {
+        let mut rules = vec![Rule::new(&self.find.0, &self.replace.0)];
+        for (index, rule) in self.rules.iter().enumerate() {
+            if rule.find.is_empty() && rule.replace.is_empty() {
+                continue;
+            }
+            if rule.find.is_empty() || rule.replace.is_empty() {
+                return Err(format!(
+                    "Rule {} needs both a find and a replace.",
+                    index + 2
+                ));
+            }
+            rules.push(rule.clone());
+        }
+        Ok(rules)
+    }
 }
 
 #[derive(Debug, Clone)]
 enum Message {
     FindChanged(String),
+    RegexToggled(bool),
+    IgnoreCaseToggled(bool),
+    WholeWordToggled(bool),
+    MultilineToggled(bool),
+    RenameToggled(bool),
     ReplaceChanged(String),
+    AddRule,
+    RemoveRule(usize),
+    RuleFindChanged(usize, String),
+    RuleReplaceChanged(usize, String),
     BrowsePath,
     UpdatePath((String, String)),
     ChangePath(String),
+    PathPicked(Option<PathBuf>),
+    FilterChanged(String),
+    HiddenToggled(bool),
+    IgnoreFilesToggled(bool),
+    FollowLinksToggled(bool),
+    MaxDepthChanged(String),
+    ExcludeDirsChanged(String),
+    WorkersChanged(String),
+    PresetNameChanged(String),
+    SavePreset,
+    LoadPreset(String),
+    ShowHistory,
+    HistoryLoaded(Result<Vec<HistoryEntry>, String>),
+    OpenHistoryEntry(usize),
     Find,
-    EnableConfirm(String),
-    Confirm(String),
+    Crawled(Result<(Previewed, Vec<PathBuf>, usize, Vec<Rename>), String>),
+    FileSearched(FileReport, Option<FileMatches>),
+    EnableConfirm,
+    FileReplaced(FileReport, Backup),
+    ReplaceDone,
+    ToggleFile(usize, bool),
+    ToggleRename(usize, bool),
+    ToggleDiff,
+    DiffReady(Result<(String, Vec<FileReport>), String>),
+    ExportPatch,
+    PatchExported(Result<Option<(String, Vec<FileReport>)>, String>),
+    ToggleLine(usize, usize, bool),
+    Confirm(String, Option<Backup>, Vec<FileReport>),
     AddText(String),
     Replace,
     Cancel,
+    Undo,
+    Undone(Result<String, String>),
     Nothing,
     EnterKeyPressed,
     TabKeyPressed,
@@ -72,26 +360,241 @@ fn do_nothing(_action: Url) -> Message {
 
 const BORDER_RADIUS: f32 = 7.5;
 
+fn markdown_view(items: &[markdown::Item]) -> Element<'_, Message> {
+    markdown::view(
+        items,
+        markdown::Settings::default(),
+        markdown::Style::from_palette(Theme::CatppuccinLatte.palette()),
+    )
+    .map(do_nothing)
+}
+
+// find/replace inputs for the rules applied after the first one
+fn rules_view(state: &State) -> Element<'_, Message> {
+    Column::with_children(state.rules.iter().enumerate().map(|(index, rule)| {
+        row![
+            text_input("Find", &rule.find)
+                .on_input_maybe(if state.editable() {
+                    Some(move |find| Message::RuleFindChanged(index, find))
+                } else {
+                    Option::None
+                })
+                .style(|theme, status: text_input::Status| {
+                    text_input::default(theme, status).set_border_radius(BORDER_RADIUS)
+                }),
+            text_input("Replace with", &rule.replace)
+                .on_input_maybe(if state.editable() {
+                    Some(move |replace| Message::RuleReplaceChanged(index, replace))
+                } else {
+                    Option::None
+                })
+                .style(|theme, status: text_input::Status| {
+                    text_input::default(theme, status).set_border_radius(BORDER_RADIUS)
+                }),
+            button("Remove")
+                .on_press_maybe(if state.editable() {
+                    Some(Message::RemoveRule(index))
+                } else {
+                    Option::None
+                })
+                .style(|theme: &Theme, status: Status| {
+                    button::secondary(theme, status).set_border_radius(BORDER_RADIUS)
+                })
+                .width(80),
+        ]
+        .spacing(10)
+        .into()
+    }))
+    .spacing(10)
+    .into()
+}
+
+// past replace operations, newest first, clicking one shows its details
+fn history_view(state: &State) -> Element<'_, Message> {
+    Column::with_children(
+        state
+            .history
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(index, entry)| {
+                button(text(presenter::history_summary(entry)))
+                    .on_press(Message::OpenHistoryEntry(index))
+                    .style(|theme: &Theme, status: Status| {
+                        button::text(theme, status).set_border_radius(BORDER_RADIUS)
+                    })
+                    .into()
+            }),
+    )
+    .spacing(5)
+    .into()
+}
+
+// checkboxes for every file and matched line, shown while waiting for confirmation
+fn preview_view(state: &State) -> Element<'_, Message> {
+    Column::with_children(state.preview.iter().enumerate().map(|(file_index, file)| {
+        column![
+            checkbox(format!("File: '{}'", file.path.display()), file.selected)
+                .on_toggle(move |selected| Message::ToggleFile(file_index, selected)),
+            Column::with_children(file.lines.iter().enumerate().map(|(line_index, line)| {
+                row![
+                    checkbox("", line.selected).on_toggle(move |selected| {
+                        Message::ToggleLine(file_index, line_index, selected)
+                    }),
+                    markdown_view(&line.markdown),
+                ]
+                .spacing(10)
+                .into()
+            }))
+            .spacing(5)
+            .padding(Padding::ZERO.left(25)),
+        ]
+        .spacing(10)
+        .into()
+    }))
+    .spacing(20)
+    .into()
+}
+
+// files per status followed by every file that could not be processed, shown
+// once the search or replace is done
+fn report_view(state: &State) -> Element<'_, Message> {
+    if state.running.is_some() || state.report.is_empty() {
+        return column![].into();
+    }
+    let statuses = [
+        FileStatus::Matched,
+        FileStatus::Replaced,
+        FileStatus::NoMatch,
+        FileStatus::Unreadable,
+        FileStatus::PermissionDenied,
+        FileStatus::NotUtf8,
+        FileStatus::Changed,
+        FileStatus::Renamed,
+        FileStatus::NotRenamed,
+    ];
+    let counts = statuses.into_iter().filter_map(|status| {
+        let reports = state.report.iter().filter(|r| r.status == status);
+        let files = reports.clone().count();
+        let matches: usize = reports.map(|r| r.matches).sum();
+        (files > 0).then(|| {
+            row![
+                text(status.to_string()).width(160),
+                text(files.to_string()).width(80),
+                text(matches.to_string()),
+            ]
+            .into()
+        })
+    });
+    let failed: Vec<Element<'_, Message>> = state
+        .report
+        .iter()
+        .filter(|r| r.status.is_error())
+        .map(|r| {
+            row![
+                text(r.status.to_string()).width(160),
+                text(
+                    r.message
+                        .clone()
+                        .unwrap_or_else(|| r.path.display().to_string())
+                ),
+            ]
+            .into()
+        })
+        .collect();
+    let summary = column![
+        row![
+            text("Status").width(160),
+            text("Files").width(80),
+            text("Matches"),
+        ],
+        Column::with_children(counts).spacing(5),
+    ]
+    .spacing(10);
+    if failed.is_empty() {
+        return summary.into();
+    }
+    summary
+        .push(text("Skipped files"))
+        .push(Column::with_children(failed).spacing(5))
+        .into()
+}
+
+fn renames_view(state: &State) -> Element<'_, Message> {
+    Column::with_children(state.renames.iter().enumerate().map(|(index, rename)| {
+        checkbox(presenter::markdown_rename(&rename.rename), rename.selected)
+            .on_toggle_maybe(if rename.rename.conflict.is_none() {
+                Some(move |selected| Message::ToggleRename(index, selected))
+            } else {
+                Option::None
+            })
+            .into()
+    }))
+    .spacing(5)
+    .into()
+}
+
 fn view(state: &State) -> Container<'_, Message> {
     container(
         column![
             row![
                 // text input for find, replace and directory
                 column![
-                    text_input("Find", &state.find.0)
-                        .id("find")
-                        .on_input_maybe(if !state.confirm {
-                            Some(Message::FindChanged)
-                        } else {
-                            Option::None
-                        })
-                        .style(|theme, status: text_input::Status| {
-                            text_input::default(theme, status).set_border_radius(BORDER_RADIUS)
-                        })
-                        .on_submit(Message::EnterKeyPressed),
+                    column![
+                        text_input("Find", &state.find.0)
+                            .id("find")
+                            .on_input_maybe(if state.editable() {
+                                Some(Message::FindChanged)
+                            } else {
+                                Option::None
+                            })
+                            .style(|theme, status: text_input::Status| {
+                                text_input::default(theme, status).set_border_radius(BORDER_RADIUS)
+                            })
+                            .on_submit(Message::EnterKeyPressed),
+                        // options for how the find pattern is matched
+                        row![
+                            checkbox("Regex", state.options.regex).on_toggle_maybe(
+                                if state.editable() {
+                                    Some(Message::RegexToggled)
+                                } else {
+                                    Option::None
+                                }
+                            ),
+                            checkbox("Ignore case", state.options.case_insensitive)
+                                .on_toggle_maybe(if state.editable() {
+                                    Some(Message::IgnoreCaseToggled)
+                                } else {
+                                    Option::None
+                                }),
+                            checkbox("Whole word", state.options.whole_word).on_toggle_maybe(
+                                if state.editable() {
+                                    Some(Message::WholeWordToggled)
+                                } else {
+                                    Option::None
+                                }
+                            ),
+                            checkbox("Multi-line (\\n)", state.options.multiline).on_toggle_maybe(
+                                if state.editable() {
+                                    Some(Message::MultilineToggled)
+                                } else {
+                                    Option::None
+                                }
+                            ),
+                            checkbox("Rename files", state.rename).on_toggle_maybe(
+                                if state.editable() {
+                                    Some(Message::RenameToggled)
+                                } else {
+                                    Option::None
+                                }
+                            ),
+                        ]
+                        .spacing(20),
+                    ]
+                    .spacing(10),
                     text_input("Replace with", &state.replace.0)
                         .id("replace")
-                        .on_input_maybe(if !state.confirm {
+                        .on_input_maybe(if state.editable() {
                             Some(Message::ReplaceChanged)
                         } else {
                             Option::None
@@ -100,10 +603,11 @@ fn view(state: &State) -> Container<'_, Message> {
                             text_input::default(theme, status).set_border_radius(BORDER_RADIUS)
                         })
                         .on_submit(Message::EnterKeyPressed),
+                    rules_view(state),
                     row![
-                        text_input("Directory", &state.path)
+                        text_input("Directory", &state.path.to_string_lossy())
                             .id("dir")
-                            .on_input_maybe(if !state.confirm {
+                            .on_input_maybe(if state.editable() {
                                 Some(Message::ChangePath)
                             } else {
                                 Option::None
@@ -113,7 +617,7 @@ fn view(state: &State) -> Container<'_, Message> {
                             })
                             .on_submit(Message::EnterKeyPressed),
                         button("Browse")
-                            .on_press_maybe(if !state.confirm {
+                            .on_press_maybe(if state.editable() {
                                 Some(Message::BrowsePath)
                             } else {
                                 Option::None
@@ -124,14 +628,130 @@ fn view(state: &State) -> Container<'_, Message> {
                             .width(80),
                     ]
                     .spacing(10),
+                    row![
+                        text_input("File filter, e.g. *.txt,*.params,!*.bak", &state.filter)
+                            .id("filter")
+                            .on_input_maybe(if state.editable() {
+                                Some(Message::FilterChanged)
+                            } else {
+                                Option::None
+                            })
+                            .style(|theme, status: text_input::Status| {
+                                text_input::default(theme, status).set_border_radius(BORDER_RADIUS)
+                            })
+                            .on_submit(Message::EnterKeyPressed),
+                        // how many files are searched or replaced at the same time
+                        text_input("Workers", &state.workers.to_string())
+                            .on_input_maybe(if state.editable() {
+                                Some(Message::WorkersChanged)
+                            } else {
+                                Option::None
+                            })
+                            .style(|theme, status: text_input::Status| {
+                                text_input::default(theme, status).set_border_radius(BORDER_RADIUS)
+                            })
+                            .width(80),
+                    ]
+                    .spacing(10),
+                    // entries the walk leaves out regardless of the file filter
+                    row![
+                        text_input(
+                            "Excluded directories, e.g. target,node_modules",
+                            &state.exclude_dirs
+                        )
+                        .on_input_maybe(if state.editable() {
+                            Some(Message::ExcludeDirsChanged)
+                        } else {
+                            Option::None
+                        })
+                        .style(|theme, status: text_input::Status| {
+                            text_input::default(theme, status).set_border_radius(BORDER_RADIUS)
+                        })
+                        .on_submit(Message::EnterKeyPressed),
+                        checkbox("Hidden files (default off)", !state.crawl.skip_hidden)
+                            .on_toggle_maybe(if state.editable() {
+                                Some(Message::HiddenToggled)
+                            } else {
+                                Option::None
+                            }),
+                        checkbox(
+                            "Use .gitignore/.frrignore (default on)",
+                            state.crawl.ignore_files
+                        )
+                        .on_toggle_maybe(if state.editable() {
+                            Some(Message::IgnoreFilesToggled)
+                        } else {
+                            Option::None
+                        }),
+                        checkbox("Follow symlinks", state.crawl.follow_links).on_toggle_maybe(
+                            if state.editable() {
+                                Some(Message::FollowLinksToggled)
+                            } else {
+                                Option::None
+                            }
+                        ),
+                        // empty for no limit
+                        text_input(
+                            "Max depth",
+                            &state
+                                .crawl
+                                .max_depth
+                                .map(|depth| depth.to_string())
+                                .unwrap_or_default()
+                        )
+                        .on_input_maybe(if state.editable() {
+                            Some(Message::MaxDepthChanged)
+                        } else {
+                            Option::None
+                        })
+                        .style(|theme, status: text_input::Status| {
+                            text_input::default(theme, status).set_border_radius(BORDER_RADIUS)
+                        })
+                        .width(100),
+                    ]
+                    .spacing(10)
+                    .align_y(iced::Alignment::Center),
+                    // saving the current rules and settings under a name, or loading them again
+                    row![
+                        text_input("Preset name", &state.preset_name)
+                            .on_input_maybe(if state.editable() {
+                                Some(Message::PresetNameChanged)
+                            } else {
+                                Option::None
+                            })
+                            .style(|theme, status: text_input::Status| {
+                                text_input::default(theme, status).set_border_radius(BORDER_RADIUS)
+                            }),
+                        button("Save preset")
+                            .on_press_maybe(if state.editable() && !state.preset_name.is_empty() {
+                                Some(Message::SavePreset)
+                            } else {
+                                Option::None
+                            })
+                            .style(|theme: &Theme, status: Status| {
+                                button::secondary(theme, status).set_border_radius(BORDER_RADIUS)
+                            }),
+                        pick_list(
+                            state
+                                .presets
+                                .iter()
+                                .map(|preset| preset.name.clone())
+                                .collect::<Vec<String>>(),
+                            None::<String>,
+                            Message::LoadPreset,
+                        )
+                        .placeholder("Load preset")
+                        .width(150),
+                    ]
+                    .spacing(10),
                 ]
                 .max_width(500)
                 .spacing(20),
                 //buttons for updating path and running the find and replace operation
-                //  will be disabled if state.confirm == true
+                //  will be disabled while a preview is shown or an operation runs
                 column![
                     button("Update path - find")
-                        .on_press_maybe(if !state.confirm {
+                        .on_press_maybe(if state.editable() {
                             Some(Message::UpdatePath(state.find.clone()))
                         } else {
                             Option::None
@@ -141,7 +761,7 @@ fn view(state: &State) -> Container<'_, Message> {
                             button::secondary(theme, status).set_border_radius(BORDER_RADIUS)
                         }),
                     button("Update path - replace")
-                        .on_press_maybe(if !state.confirm {
+                        .on_press_maybe(if state.editable() {
                             Some(Message::UpdatePath(state.replace.clone()))
                         } else {
                             Option::None
@@ -150,8 +770,18 @@ fn view(state: &State) -> Container<'_, Message> {
                         .style(|theme, status| {
                             button::secondary(theme, status).set_border_radius(BORDER_RADIUS)
                         }),
+                    button("Add rule")
+                        .on_press_maybe(if state.editable() {
+                            Some(Message::AddRule)
+                        } else {
+                            Option::None
+                        })
+                        .width(220)
+                        .style(|theme, status| {
+                            button::secondary(theme, status).set_border_radius(BORDER_RADIUS)
+                        }),
                     button("Find")
-                        .on_press_maybe(if !state.confirm {
+                        .on_press_maybe(if state.editable() {
                             Some(Message::Find)
                         } else {
                             Option::None
@@ -167,12 +797,21 @@ fn view(state: &State) -> Container<'_, Message> {
             // Container to display all the actions
             container(
                 scrollable(
-                    markdown::view(
-                        &state.markdown,
-                        markdown::Settings::default(),
-                        markdown::Style::from_palette(Theme::CatppuccinLatte.palette()),
-                    )
-                    .map(do_nothing)
+                    column![
+                        markdown_view(&state.markdown),
+                        report_view(state),
+                        match &state.diff {
+                            Some(diff) => markdown_view(diff),
+                            None => preview_view(state),
+                        },
+                        renames_view(state),
+                        if state.show_history {
+                            history_view(state)
+                        } else {
+                            column![].into()
+                        },
+                    ]
+                    .spacing(20)
                 )
                 .id(Id::new("scrollable"))
             )
@@ -193,7 +832,7 @@ fn view(state: &State) -> Container<'_, Message> {
                         button::primary(theme, status).set_border_radius(BORDER_RADIUS)
                     }),
                 button("Cancel")
-                    .on_press_maybe(if state.confirm {
+                    .on_press_maybe(if state.confirm || state.running.is_some() {
                         Some(Message::Cancel)
                     } else {
                         Option::None
@@ -201,6 +840,50 @@ fn view(state: &State) -> Container<'_, Message> {
                     .style(|theme: &Theme, status: Status| {
                         button::secondary(theme, status).set_border_radius(BORDER_RADIUS)
                     }),
+                button(if state.diff.is_some() {
+                    "Hide diff"
+                } else {
+                    "Show diff"
+                })
+                .on_press_maybe(if state.confirm {
+                    Some(Message::ToggleDiff)
+                } else {
+                    Option::None
+                })
+                .style(|theme: &Theme, status: Status| {
+                    button::secondary(theme, status).set_border_radius(BORDER_RADIUS)
+                }),
+                button("Export patch")
+                    .on_press_maybe(if state.confirm {
+                        Some(Message::ExportPatch)
+                    } else {
+                        Option::None
+                    })
+                    .style(|theme: &Theme, status: Status| {
+                        button::secondary(theme, status).set_border_radius(BORDER_RADIUS)
+                    }),
+                button("Undo last replace")
+                    .on_press_maybe(if state.editable() && state.last_backup.is_some() {
+                        Some(Message::Undo)
+                    } else {
+                        Option::None
+                    })
+                    .style(|theme: &Theme, status: Status| {
+                        button::secondary(theme, status).set_border_radius(BORDER_RADIUS)
+                    }),
+                button(if state.show_history {
+                    "Hide history"
+                } else {
+                    "History"
+                })
+                .on_press_maybe(if state.editable() {
+                    Some(Message::ShowHistory)
+                } else {
+                    Option::None
+                })
+                .style(|theme: &Theme, status: Status| {
+                    button::secondary(theme, status).set_border_radius(BORDER_RADIUS)
+                }),
             ]
             .height(40)
             .spacing(20)
@@ -208,7 +891,6 @@ fn view(state: &State) -> Container<'_, Message> {
         .spacing(15)
         .padding(20),
     )
-    .into()
 }
 
 fn update(state: &mut State, message: Message) -> Task<Message> {
@@ -219,99 +901,566 @@ fn update(state: &mut State, message: Message) -> Task<Message> {
             Task::none()
         }
 
+        // event handling for the match option checkboxes
+        Message::RegexToggled(regex) => {
+            state.options.regex = regex;
+            Task::none()
+        }
+
+        Message::IgnoreCaseToggled(case_insensitive) => {
+            state.options.case_insensitive = case_insensitive;
+            Task::none()
+        }
+
+        Message::WholeWordToggled(whole_word) => {
+            state.options.whole_word = whole_word;
+            Task::none()
+        }
+
+        Message::MultilineToggled(multiline) => {
+            state.options.multiline = multiline;
+            Task::none()
+        }
+
+        Message::RenameToggled(rename) => {
+            state.rename = rename;
+            Task::none()
+        }
+
         // event handling for the replace text input
         Message::ReplaceChanged(replace) => {
             state.replace.0 = replace.trim().to_owned();
             Task::none()
         }
 
+        // event handling for the extra rule rows
+        Message::AddRule => {
+            state.rules.push(Rule::default());
+            Task::none()
+        }
+
+        Message::RemoveRule(index) => {
+            if index < state.rules.len() {
+                state.rules.remove(index);
+            }
+            Task::none()
+        }
+
+        Message::RuleFindChanged(index, find) => {
+            if let Some(rule) = state.rules.get_mut(index) {
+                rule.find = find.trim().to_owned();
+            }
+            Task::none()
+        }
+
+        Message::RuleReplaceChanged(index, replace) => {
+            if let Some(rule) = state.rules.get_mut(index) {
+                rule.replace = replace.trim().to_owned();
+            }
+            Task::none()
+        }
+
         // event handling for the directory text input
         Message::ChangePath(dir) => {
-            state.path = dir.trim().to_owned();
+            state.path = PathBuf::from(dir.trim());
             state.update_markdown();
             Task::none()
         }
 
+        // event handling for the file filter text input
+        Message::FilterChanged(filter) => {
+            state.filter = filter.trim().to_owned();
+            Task::none()
+        }
+
+        Message::HiddenToggled(hidden) => {
+            state.crawl.skip_hidden = !hidden;
+            Task::none()
+        }
+
+        Message::IgnoreFilesToggled(ignore_files) => {
+            state.crawl.ignore_files = ignore_files;
+            Task::none()
+        }
+
+        Message::FollowLinksToggled(follow_links) => {
+            state.crawl.follow_links = follow_links;
+            Task::none()
+        }
+
+        // an empty input removes the limit, anything else but a number is ignored
+        Message::MaxDepthChanged(depth) => {
+            let depth = depth.trim();
+            if depth.is_empty() {
+                state.crawl.max_depth = None;
+            } else if let Ok(depth) = depth.parse::<usize>() {
+                state.crawl.max_depth = Some(depth);
+            }
+            Task::none()
+        }
+
+        Message::ExcludeDirsChanged(names) => {
+            state.crawl.exclude_dirs = parse_dir_list(&names);
+            state.exclude_dirs = names;
+            Task::none()
+        }
+
+        // anything but a positive number is ignored
+        Message::WorkersChanged(workers) => {
+            if let Ok(workers) = workers.trim().parse::<usize>() {
+                state.workers = workers.max(1);
+            }
+            Task::none()
+        }
+
+        // event handling for the presets
+        Message::PresetNameChanged(name) => {
+            state.preset_name = name.trim().to_owned();
+            Task::none()
+        }
+
+        Message::SavePreset => {
+            let Some(path) = state.presets_path.clone() else {
+                return Task::done(Message::AddText(
+                    "Presets can not be saved without a home directory.".to_owned(),
+                ));
+            };
+            let mut rules = vec![Rule::new(&state.find.0, &state.replace.0)];
+            rules.extend(state.rules.iter().cloned());
+            let name = state.preset_name.clone();
+            preset::upsert_preset(
+                &mut state.presets,
+                Preset {
+                    name: name.clone(),
+                    rules,
+                    dir: state.path.to_string_lossy().into_owned(),
+                    filter: state.filter.clone(),
+                    options: state.options,
+                    crawl: state.crawl.clone(),
+                },
+            );
+            Task::perform(
+                preset::save_presets(path, state.presets.clone()),
+                move |result| match result {
+                    Ok(()) => Message::AddText(format!("Saved preset '{}'.", name)),
+                    Err(e) => Message::AddText(format!("{:#}", e)),
+                },
+            )
+        }
+
+        // the first rule of a preset goes into the find and replace inputs
+        Message::LoadPreset(name) => {
+            if !state.editable() {
+                return Task::none();
+            }
+            let Some(preset) = state.presets.iter().find(|p| p.name == name).cloned() else {
+                return Task::none();
+            };
+            let mut rules = preset.rules.into_iter();
+            let first = rules.next().unwrap_or_default();
+            state.find.0 = first.find;
+            state.replace.0 = first.replace;
+            state.rules = rules.collect();
+            state.path = PathBuf::from(preset.dir);
+            state.filter = preset.filter;
+            state.options = preset.options;
+            state.exclude_dirs = preset.crawl.exclude_dirs.join(", ");
+            state.crawl = preset.crawl;
+            state.preset_name = preset.name;
+            Task::done(Message::AddText(format!("Loaded preset '{}'.", name)))
+        }
+
         // event handling for the browse button
-        Message::BrowsePath => {
-            return Task::perform(AsyncFileDialog::new().pick_folder(), |path| {
-                Message::ChangePath(path.unwrap().path().display().to_string())
-            });
+        Message::BrowsePath => Task::perform(AsyncFileDialog::new().pick_folder(), |folder| {
+            Message::PathPicked(folder.map(|folder| folder.path().to_owned()))
+        }),
+
+        // the picked folder is kept as is, the dialog may also be closed without one
+        Message::PathPicked(folder) => {
+            if let Some(folder) = folder {
+                state.path = folder;
+            }
+            Task::none()
         }
 
-        // update path based on updated find or replace strings
+        // update path based on updated find or replace strings, a path that is
+        // not valid UTF-8 can only be changed by hand
         Message::UpdatePath(slice) => {
-            let cloned_path = state.path.clone();
-            if !cloned_path.contains(&slice.1) || slice.1 == "" {
-                state.text =
-                    format!("Could not update the path automatically, please update it manually.",);
+            match state.path.to_str().map(str::to_owned) {
+                Some(cloned_path) if cloned_path.contains(&slice.1) && !slice.1.is_empty() => {
+                    state.path = PathBuf::from(cloned_path.replace(&slice.1, &slice.0));
+                }
+                _ => {
+                    state.text =
+                        "Could not update the path automatically, please update it manually."
+                            .to_string();
+                }
             }
             state.update_markdown();
-            let new_path = cloned_path.replace(&slice.1, &slice.0);
-            state.path = new_path;
             Task::none()
         }
 
+        // toggles the list of past replace operations, reloading the journal
+        Message::ShowHistory => {
+            if state.show_history {
+                state.show_history = false;
+                return Task::none();
+            }
+            match state.history_path.clone() {
+                Some(path) => Task::perform(history::load_history(path), |history| {
+                    Message::HistoryLoaded(history.map_err(|e| format!("{:#}", e)))
+                }),
+                None => Task::done(Message::AddText(
+                    "The history is not available without a home directory.".to_owned(),
+                )),
+            }
+        }
+
+        Message::HistoryLoaded(history) => match history {
+            Ok(history) => {
+                state.show_history = true;
+                let heading = if history.is_empty() {
+                    "### No replace operations recorded yet".to_owned()
+                } else {
+                    "### History, select an operation to show its details".to_owned()
+                };
+                state.history = history;
+                Task::done(Message::AddText(heading))
+            }
+            Err(e) => Task::done(Message::AddText(e)),
+        },
+
+        Message::OpenHistoryEntry(index) => match state.history.get(index) {
+            Some(entry) => {
+                state.show_history = false;
+                Task::done(Message::AddText(presenter::markdown_history_entry(entry)))
+            }
+            None => Task::none(),
+        },
+
         // event handling for the provisional replace results
         Message::Find => {
+            if state.running.is_some() {
+                return Task::none();
+            }
             state.confirm = false;
-            if state.find.0 == "" || state.replace.0 == "" || state.path == "" {
+            state.show_history = false;
+            state.diff = None;
+            if state.find.0.is_empty()
+                || state.replace.0.is_empty()
+                || state.path.as_os_str().is_empty()
+            {
                 return Task::done(Message::AddText(
                     "Please enter all three required parameters.".to_owned(),
                 ));
-            } else if state.path.chars().next().unwrap() != '/' {
-                return Task::done(Message::AddText(
-                    "Please enter an absolute path.".to_owned(),
-                ));
             }
+            let root = match state.root() {
+                Ok(root) => root,
+                Err(e) => return Task::done(Message::AddText(e)),
+            };
+            let rules = match state.all_rules() {
+                Ok(rules) => rules,
+                Err(e) => return Task::done(Message::AddText(e)),
+            };
+            let filter = match FileFilter::parse(&state.filter) {
+                Ok(filter) => filter,
+                Err(e) => return Task::done(Message::AddText(format!("{}", e))),
+            };
+            // fails on an invalid pattern before any file is searched
+            let compiled = match compile_rules(&rules, &state.options) {
+                Ok(compiled) => Arc::new(compiled),
+                Err(e) => return Task::done(Message::AddText(format!("{:#}", e))),
+            };
             state.text = "# Searching...".to_owned();
             state.update_markdown();
-            match dir_crawl(&state.path) {
-                Ok(list) => {
-                    state.file_list = list.clone();
-                    return Task::perform(
-                        find_from_vec(
-                            state.find.0.to_owned(),
-                            state.replace.0.to_owned(),
-                            state.file_list.clone(),
-                        ),
-                        |text| match text {
-                            Ok(text) => {
-                                return Message::EnableConfirm(text);
-                            }
-                            Err(e) => return Message::AddText(format!("{}", e)),
-                        },
-                    );
-                }
-                Err(e) => {
-                    eprintln!("There was a problem searching for txt files: {}", e);
-                }
+            state.preview.clear();
+            state.previewed = None;
+            state.renames.clear();
+            state.report.clear();
+            let cancel = CancelFlag::default();
+            state.running = Some(cancel.clone());
+            // walking the tree blocks, so it runs off the UI thread
+            let previewed = Previewed {
+                rules,
+                compiled,
+                options: state.options,
+                root,
+            };
+            let rename = state.rename;
+            let crawl = state.crawl.clone();
+            Task::perform(
+                async move {
+                    let Previewed {
+                        rules,
+                        options,
+                        root,
+                        ..
+                    } = previewed.clone();
+                    let (files, skipped) =
+                        dir_crawl_async(root.clone(), filter, crawl.clone(), cancel.clone())
+                            .await?;
+                    // names are matched regardless of the file filter, both walks
+                    // run into the same unreadable entries
+                    let (renames, skipped) = if rename && !cancel.is_cancelled() {
+                        let (renames, rename_skipped) = tokio::task::spawn_blocking(move || {
+                            find_renames(&root, &rules, &options, &crawl)
+                        })
+                        .await??;
+                        (renames, skipped.max(rename_skipped))
+                    } else {
+                        (vec![], skipped)
+                    };
+                    Ok::<_, Error>((previewed, files, skipped, renames))
+                },
+                |result| Message::Crawled(result.map_err(|e| format!("{:#}", e))),
+            )
+        }
+
+        // searches the crawled files, every file with a match shows up in the
+        // preview as soon as it is done
+        Message::Crawled(result) => match result {
+            _ if state.running.as_ref().is_none_or(CancelFlag::is_cancelled) => {
+                state.running = None;
+                Task::done(Message::AddText("Search cancelled.".to_owned()))
+            }
+            Ok((previewed, files, skipped, renames)) => {
+                state.file_list = files.clone();
+                state.skipped = skipped;
+                state.progress = Progress::new(files.len());
+                state.renames = renames
+                    .into_iter()
+                    .map(|rename| PreviewRename {
+                        selected: rename.conflict.is_none(),
+                        rename,
+                    })
+                    .collect();
+                let cancel = state.running.clone().unwrap_or_default();
+                let (rules, options) = (previewed.compiled.clone(), previewed.options);
+                state.previewed = Some(previewed);
+                Task::run(
+                    find_stream(rules, files, options, state.workers, cancel),
+                    |(path, result)| {
+                        let report = FileReport::searched(path, &result);
+                        Message::FileSearched(report, result.ok().flatten())
+                    },
+                )
+                .chain(Task::done(Message::EnableConfirm))
+            }
+            Err(e) => {
+                state.running = None;
+                Task::done(Message::AddText(e))
+            }
+        },
+
+        Message::FileSearched(report, file) => {
+            if let Some(file) = file {
+                state.preview.push(PreviewFile::from(file));
             }
+            state.progress.update(&report.path, report.matches);
+            state.report.push(report);
+            state.text = state.progress.markdown("Searching...");
             state.update_markdown();
             Task::none()
         }
 
-        Message::EnableConfirm(text) => {
+        // the search finished or was cancelled
+        Message::EnableConfirm => {
+            if state
+                .running
+                .take()
+                .is_none_or(|cancel| cancel.is_cancelled())
+            {
+                state.preview.clear();
+                state.previewed = None;
+                state.renames.clear();
+                return Task::done(Message::AddText("Search cancelled.".to_owned()));
+            }
+            if state.preview.is_empty() && state.renames.is_empty() {
+                return Task::done(Message::AddText("Nothing found".to_owned()));
+            }
             state.confirm = true;
-            Task::done(Message::AddText(text))
+            // files finish in any order
+            state.preview.sort_by(|a, b| a.path.cmp(&b.path));
+            let count: usize = state.preview.iter().map(|file| file.matches).sum();
+            let heading = if state.rename {
+                format!(
+                    "### Found {} matches in {} files and {} names to rename, select the ones to replace",
+                    count,
+                    state.preview.len(),
+                    state.renames.len()
+                )
+            } else {
+                format!(
+                    "### Found {} matches in {} files, select the ones to replace",
+                    count,
+                    state.preview.len()
+                )
+            };
+            let heading = if state.skipped > 0 {
+                format!(
+                    "{}\n\n{} entries could not be read and were skipped.",
+                    heading, state.skipped
+                )
+            } else {
+                heading
+            };
+            Task::done(Message::AddText(heading))
+        }
+
+        Message::ToggleRename(index, selected) => {
+            if let Some(rename) = state.renames.get_mut(index) {
+                rename.selected = selected && rename.rename.conflict.is_none();
+            }
+            Task::none()
+        }
+
+        // selecting a file (de)selects all of its lines
+        Message::ToggleFile(file_index, selected) => {
+            if let Some(file) = state.preview.get_mut(file_index) {
+                file.selected = selected;
+                file.lines
+                    .iter_mut()
+                    .for_each(|line| line.selected = selected);
+            }
+            Task::none()
+        }
+
+        Message::ToggleLine(file_index, line_index, selected) => {
+            if let Some(file) = state.preview.get_mut(file_index) {
+                if let Some(line) = file.lines.get_mut(line_index) {
+                    line.selected = selected;
+                }
+                file.selected = file.lines.iter().any(|line| line.selected);
+            }
+            Task::none()
+        }
+
+        // switches the preview between the checkboxes and a unified diff of the
+        // selected changes
+        Message::ToggleDiff => {
+            if state.diff.take().is_some() {
+                return Task::none();
+            }
+            let Some(Previewed {
+                rules,
+                options,
+                root,
+                ..
+            }) = state.previewed.clone()
+            else {
+                return Task::none();
+            };
+            Task::perform(
+                diff_from_vec(rules, state.replace_targets(), options, root),
+                |diff| Message::DiffReady(diff.map_err(|e| format!("{:#}", e))),
+            )
+        }
+
+        Message::DiffReady(diff) => match diff {
+            Ok((diff, skipped)) => {
+                // carriage returns would show up as stray characters
+                let diff = format!("```diff\n{}```", diff.replace('\r', ""));
+                state.diff = Some(markdown::parse(&diff).collect());
+                if skipped.is_empty() {
+                    return Task::none();
+                }
+                Task::done(Message::AddText(format!(
+                    "Left out of the diff:\n\n{}",
+                    presenter::markdown_report(&skipped)
+                )))
+            }
+            Err(e) => Task::done(Message::AddText(e)),
+        },
+
+        // saves the selected changes as a patch to apply with `patch -p0` from the directory
+        Message::ExportPatch => {
+            let Some(Previewed {
+                rules,
+                options,
+                root: dir,
+                ..
+            }) = state.previewed.clone()
+            else {
+                return Task::none();
+            };
+            let targets = state.replace_targets();
+            Task::perform(
+                async move {
+                    let (patch, skipped) =
+                        diff_from_vec(rules, targets, options, dir.clone()).await?;
+                    let Some(file) = AsyncFileDialog::new()
+                        .set_directory(&dir)
+                        .set_file_name("changes.patch")
+                        .save_file()
+                        .await
+                    else {
+                        return Ok(None);
+                    };
+                    tokio::fs::write(file.path(), patch).await?;
+                    Ok::<_, Error>(Some((file.path().display().to_string(), skipped)))
+                },
+                |result| Message::PatchExported(result.map_err(|e| format!("{:#}", e))),
+            )
         }
 
-        Message::Confirm(text) => {
+        Message::PatchExported(result) => match result {
+            Ok(Some((path, skipped))) => {
+                let mut text = format!(
+                    "Saved the patch to '{}', apply it from the directory with `patch -p0`.",
+                    path
+                );
+                if !skipped.is_empty() {
+                    text.push_str("\n\nLeft out of the patch:\n\n");
+                    text.push_str(&presenter::markdown_report(&skipped));
+                }
+                Task::done(Message::AddText(text))
+            }
+            Ok(None) => Task::none(),
+            Err(e) => Task::done(Message::AddText(e)),
+        },
+
+        Message::Confirm(text, backup, reports) => {
+            state.report.extend(reports);
             state.confirm = false;
-            Task::done(Message::AddText(format!(
-                "Replaced '{}' with '{}' in the following files: {}",
-                state.find.0, state.replace.0, text
-            )))
+            // only the last replace can be undone, older originals are not needed
+            let mut cleanup = Task::none();
+            if let Some(backup) = backup.filter(|backup| !backup.is_empty()) {
+                if let Some(previous) = state.last_backup.replace(backup) {
+                    cleanup = Task::future(previous.remove()).discard();
+                }
+            }
+            let rules = state
+                .previewed
+                .take()
+                .map(|previewed| previewed.rules)
+                .unwrap_or_default();
+            cleanup.chain(Task::done(Message::AddText(format!(
+                "Replaced {} in the following files: {}",
+                presenter::describe_rules(&rules),
+                text
+            ))))
         }
 
         Message::AddText(text) => {
-            state.text = format!("{}", text);
+            state.text = text.to_string();
             state.update_markdown();
             Task::none()
         }
 
         // event handling for the completion of the replace operation
         Message::Replace => {
+            if !state.confirm {
+                return Task::none();
+            }
+            let Some(Previewed {
+                rules,
+                compiled,
+                options,
+                root,
+            }) = state.previewed.clone()
+            else {
+                return Task::none();
+            };
             state.text = "# Replacing...".to_owned();
             state.update_markdown();
             // saving the find and replace for the path formatting
@@ -320,26 +1469,162 @@ fn update(state: &mut State, message: Message) -> Task<Message> {
 
             state.confirm = false;
 
-            return Task::perform(
-                replace_from_vec(
-                    state.find.0.to_owned(),
-                    state.replace.0.to_owned(),
-                    state.file_list.clone(),
-                ),
-                |text| match text {
-                    Ok(text) => return Message::Confirm(text),
-                    Err(e) => Message::Confirm(format!("{}", e)),
+            // only the matches still selected in the preview get replaced
+            let targets = state.replace_targets();
+            state.preview.clear();
+            state.diff = None;
+            let renames: Vec<Rename> = state
+                .renames
+                .drain(..)
+                .filter(|rename| rename.selected)
+                .map(|rename| rename.rename)
+                .collect();
+
+            let cancel = CancelFlag::default();
+            let backup = Backup::in_dir(&state.backup_dir);
+            state.running = Some(cancel.clone());
+            state.progress = Progress::new(targets.len());
+            state.report.clear();
+            let task = Task::run(
+                replace_stream(compiled, targets, options, state.workers, &backup, cancel),
+                |(path, part, result)| {
+                    Message::FileReplaced(FileReport::replaced(path, &result), part)
                 },
-            );
+            )
+            .chain(Task::done(Message::ReplaceDone));
+            state.replacing = Some(ReplaceRun {
+                rules,
+                root,
+                backup,
+                renames,
+            });
+            task
+        }
+
+        Message::FileReplaced(report, part) => {
+            let Some(run) = state.replacing.as_mut() else {
+                return Task::none();
+            };
+            run.backup.merge(part);
+            state.progress.update(&report.path, report.matches);
+            state.report.push(report);
+            state.text = state.progress.markdown("Replacing...");
+            state.update_markdown();
+            Task::none()
+        }
+
+        // renames and records the replace once every file is done, a cancelled
+        // replace keeps what was already modified so it can still be undone
+        Message::ReplaceDone => {
+            let cancelled = state
+                .running
+                .take()
+                .is_some_and(|cancel| cancel.is_cancelled());
+            let Some(run) = state.replacing.take() else {
+                return Task::none();
+            };
+            state.report.sort_by(|a, b| a.path.cmp(&b.path));
+            let files: Vec<_> = state
+                .report
+                .iter()
+                .filter_map(FileReport::replaced_file)
+                .collect();
+            let dir = run.root.clone();
+            let history_path = state.history_path.clone();
+            Task::perform(
+                async move {
+                    let mut text: String = files
+                        .iter()
+                        .map(|file| format!("\n- '{}'\n", file.path.display()))
+                        .collect();
+                    let mut backup = run.backup;
+                    let mut reports = vec![];
+                    if cancelled {
+                        text = format!(
+                            "{}\n\nThe replace was cancelled, the remaining files were left untouched.",
+                            text
+                        );
+                    } else if !run.renames.is_empty() {
+                        // contents first, the renames move the files they were found in
+                        match rename_all(run.renames, &mut backup).await {
+                            Ok((renamed, rename_reports)) => {
+                                text = format!(
+                                    "{}\n\nRenamed the following files and directories: {}",
+                                    text, renamed
+                                );
+                                reports = rename_reports;
+                            }
+                            Err(e) => text = format!("{}\n\n{:#}", text, e),
+                        }
+                    }
+                    // the files were already changed, so a failing journal is only reported
+                    if let Some(path) = history_path {
+                        let entry =
+                            HistoryEntry::new(&dir, run.rules, files, backup.renames.clone());
+                        if let Err(e) = history::append_entry(path, entry).await {
+                            text = format!(
+                                "{}\n\nCould not record the replace in the history: {:#}",
+                                text, e
+                            );
+                        }
+                    }
+                    (text, backup, reports)
+                },
+                |(text, backup, reports)| Message::Confirm(text, Some(backup), reports),
+            )
         }
 
         Message::Cancel => {
+            // a running operation finishes the files it already started
+            if let Some(cancel) = &state.running {
+                cancel.cancel();
+                state.text = "# Cancelling...".to_owned();
+                state.update_markdown();
+                return Task::none();
+            }
             state.confirm = false;
-            state.text = format!("Operation cancelled.");
+            state.preview.clear();
+            state.previewed = None;
+            state.renames.clear();
+            state.diff = None;
+            state.text = "Operation cancelled.".to_string();
             state.update_markdown();
             Task::none()
         }
 
+        // restore the files touched by the last replace from their backup
+        Message::Undo => match state.last_backup.clone() {
+            Some(backup) => {
+                state.text = "# Undoing...".to_owned();
+                state.update_markdown();
+                Task::perform(backup.restore(), |result| {
+                    Message::Undone(
+                        result
+                            .map(|files| {
+                                let list: String = files
+                                    .iter()
+                                    .map(|file| format!("- '{}'\n", file.display()))
+                                    .collect();
+                                format!("\n{}", list)
+                            })
+                            .map_err(|e| format!("{}", e)),
+                    )
+                })
+            }
+            None => Task::none(),
+        },
+
+        Message::Undone(result) => match result {
+            Ok(files) => {
+                state.last_backup = None;
+                Task::done(Message::AddText(format!(
+                    "Restored the following files: {}",
+                    files
+                )))
+            }
+            Err(e) => Task::done(Message::AddText(e)),
+        },
+
         Message::EnterKeyPressed => {
             if state.confirm {
                 return Task::done(Message::Replace);
@@ -348,7 +1633,7 @@ fn update(state: &mut State, message: Message) -> Task<Message> {
         }
 
         Message::TabKeyPressed => {
-            let ids = vec!["replace", "dir", "find"];
+            let ids = vec!["replace", "dir", "filter", "find"];
             let mut ids_iter = ids.clone().into_iter();
             loop {
                 match ids_iter.next() {
@@ -356,14 +1641,14 @@ fn update(state: &mut State, message: Message) -> Task<Message> {
                         if id == state.focus {
                             state.focus = ids_iter
                                 .next()
-                                .or_else(|| Some(ids.get(0).unwrap()))
+                                .or_else(|| Some(ids.first().unwrap()))
                                 .unwrap()
                                 .to_string();
                             break;
                         }
                     }
                     None => {
-                        state.focus = ids.get(0).unwrap().to_string();
+                        state.focus = ids.first().unwrap().to_string();
                         break;
                     }
                 }
@@ -375,16 +1660,16 @@ fn update(state: &mut State, message: Message) -> Task<Message> {
         Message::MoveDown => scroll_by(
             Id::new("scrollable"),
             AbsoluteOffset {
-                x: 0.0 as f32,
-                y: 15.0 as f32,
+                x: 0.0_f32,
+                y: 15.0_f32,
             },
         ),
 
         Message::MoveUp => scroll_by(
             Id::new("scrollable"),
             AbsoluteOffset {
-                x: 0.0 as f32,
-                y: -15.0 as f32,
+                x: 0.0_f32,
+                y: -15.0_f32,
             },
         ),
 
@@ -409,16 +1694,444 @@ fn subscription(_state: &State) -> iced::Subscription<Message> {
     keyboard::on_key_press(handle_hotkey)
 }
 
-fn main() -> iced::Result {
-    // let args = Args::parse();
+fn main() -> Result<(), Error> {
+    let args = Args::parse();
 
-    // find_and_replace(&args.find, &args.replace_with, &args.directory).unwrap();
-    iced::application("Recursive find and replace for .txt files", update, view)
+    match args.command {
+        // run headless, e.g. over SSH
+        Some(command) => tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(cli::run(command)),
+        None => Ok(gui()?),
+    }
+}
+
+fn gui() -> iced::Result {
+    iced::application("Recursive find and replace", update, view)
         .subscription(subscription)
         .theme(|_| Theme::CatppuccinLatte)
         .window_size(Size {
             width: 1200.0,
             height: 650.0,
         })
-        .run()
+        .run_with(|| (State::with_config(), Task::none()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{update, FileStatus, Message, State};
+    use iced::futures::StreamExt;
+    use iced_runtime::Action;
+    use std::collections::VecDeque;
+    use std::fs;
+
+    // processes the message like the iced runtime would, feeding every message
+    // produced by the returned tasks back into `update`
+    async fn run(state: &mut State, message: Message) {
+        let mut queue = VecDeque::from([message]);
+        while let Some(message) = queue.pop_front() {
+            let task = update(state, message);
+            if let Some(stream) = iced_runtime::task::into_stream(task) {
+                let actions: Vec<Action<Message>> = stream.collect().await;
+                queue.extend(actions.into_iter().filter_map(|action| match action {
+                    Action::Output(message) => Some(message),
+                    _ => None,
+                }));
+            }
+        }
+    }
+
+    fn searching(dir: &tempfile::TempDir) -> State {
+        fs::write(dir.path().join("a.txt"), "izak\nizak\n").unwrap();
+        fs::write(dir.path().join("b.txt"), "nothing\n").unwrap();
+        State {
+            find: ("izak".to_owned(), "".to_owned()),
+            replace: ("tina".to_owned(), "".to_owned()),
+            path: dir.path().to_owned(),
+            // hidden, so later searches of the same directory skip it
+            backup_dir: dir.path().join(".backups"),
+            ..State::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn find_requires_all_parameters() {
+        let mut state = State::default();
+
+        run(&mut state, Message::Find).await;
+
+        assert!(!state.confirm);
+        assert_eq!(state.text, "Please enter all three required parameters.");
+    }
+
+    #[tokio::test]
+    async fn find_shows_preview_and_waits_for_confirmation() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = searching(&dir);
+
+        run(&mut state, Message::Find).await;
+
+        assert!(state.confirm);
+        assert_eq!(state.file_list.len(), 2);
+        assert_eq!(state.preview.len(), 1);
+        assert_eq!(state.preview[0].lines.len(), 2);
+        assert!(state.text.contains("Found 2 matches in 1 files"));
+    }
+
+    #[tokio::test]
+    async fn unreadable_files_are_reported_after_the_search() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = searching(&dir);
+        fs::write(dir.path().join("c.txt"), [0x69, 0xff, 0xfe]).unwrap();
+
+        run(&mut state, Message::Find).await;
+
+        assert!(state.confirm);
+        let mut statuses: Vec<(String, FileStatus)> = state
+            .report
+            .iter()
+            .map(|r| {
+                (
+                    r.path.file_name().unwrap().to_string_lossy().into_owned(),
+                    r.status,
+                )
+            })
+            .collect();
+        statuses.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            statuses,
+            vec![
+                ("a.txt".to_owned(), FileStatus::Matched),
+                ("b.txt".to_owned(), FileStatus::NoMatch),
+                ("c.txt".to_owned(), FileStatus::NotUtf8),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn picked_folders_are_searched_as_they_are() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join(OsStr::from_bytes(b"izak\xff"));
+        fs::create_dir(&folder).unwrap();
+        fs::write(folder.join("a.txt"), "izak\n").unwrap();
+        let mut state = searching(&dir);
+
+        run(&mut state, Message::PathPicked(Some(folder.clone()))).await;
+        // a closed dialog keeps the folder
+        run(&mut state, Message::PathPicked(None)).await;
+        run(&mut state, Message::Find).await;
+
+        assert_eq!(state.path, folder);
+        assert!(state.confirm);
+        assert_eq!(state.preview.len(), 1);
+        assert!(state.preview[0].path.starts_with(&folder));
+    }
+
+    #[tokio::test]
+    async fn replace_applies_selected_matches() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = searching(&dir);
+
+        run(&mut state, Message::Find).await;
+        run(&mut state, Message::ToggleLine(0, 1, false)).await;
+        run(&mut state, Message::Replace).await;
+
+        assert!(!state.confirm);
+        assert!(state.preview.is_empty());
+        assert!(state.last_backup.is_some());
+        assert_eq!(
+            (state.find.1.as_str(), state.replace.1.as_str()),
+            ("izak", "tina")
+        );
+        assert!(state.text.starts_with("Replaced 'izak' with 'tina'"));
+        let a = dir.path().join("a.txt");
+        assert_eq!(fs::read_to_string(&a).unwrap(), "tina\nizak\n");
+
+        run(&mut state, Message::Undo).await;
+        assert!(state.last_backup.is_none());
+        assert_eq!(fs::read_to_string(&a).unwrap(), "izak\nizak\n");
+    }
+
+    #[test]
+    fn inputs_are_locked_while_searching() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = searching(&dir);
+
+        // only starts the crawl, the search itself is still to come
+        let _ = update(&mut state, Message::Find);
+
+        assert!(state.running.is_some());
+        assert!(!state.editable());
+    }
+
+    #[tokio::test]
+    async fn replace_applies_the_previewed_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = searching(&dir);
+
+        run(&mut state, Message::Find).await;
+        assert!(!state.editable());
+        run(&mut state, Message::FindChanged("nothing".to_owned())).await;
+        run(&mut state, Message::ReplaceChanged("other".to_owned())).await;
+        run(&mut state, Message::RegexToggled(true)).await;
+        run(&mut state, Message::Replace).await;
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "tina\ntina\n"
+        );
+        assert!(state.text.starts_with("Replaced 'izak' with 'tina'"));
+    }
+
+    #[tokio::test]
+    async fn a_new_replace_removes_the_previous_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = searching(&dir);
+
+        run(&mut state, Message::Find).await;
+        run(&mut state, Message::Replace).await;
+        let first = state.last_backup.clone().unwrap().dir;
+        assert!(first.exists());
+
+        run(&mut state, Message::FindChanged("tina".to_owned())).await;
+        run(&mut state, Message::ReplaceChanged("izak".to_owned())).await;
+        run(&mut state, Message::Find).await;
+        run(&mut state, Message::Replace).await;
+
+        assert!(!first.exists());
+        assert!(state.last_backup.unwrap().dir.exists());
+    }
+
+    #[tokio::test]
+    async fn cancel_leaves_files_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = searching(&dir);
+
+        run(&mut state, Message::Find).await;
+        run(&mut state, Message::Cancel).await;
+
+        assert!(!state.confirm);
+        assert!(state.preview.is_empty());
+        assert_eq!(state.text, "Operation cancelled.");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "izak\nizak\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn extra_rules_are_applied_in_the_same_replace() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = searching(&dir);
+
+        run(&mut state, Message::AddRule).await;
+        run(
+            &mut state,
+            Message::RuleFindChanged(0, "nothing".to_owned()),
+        )
+        .await;
+        run(&mut state, Message::Find).await;
+        assert_eq!(state.text, "Rule 2 needs both a find and a replace.");
+
+        run(
+            &mut state,
+            Message::RuleReplaceChanged(0, "something".to_owned()),
+        )
+        .await;
+        run(&mut state, Message::Find).await;
+        assert_eq!(state.preview.len(), 2);
+
+        run(&mut state, Message::Replace).await;
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "tina\ntina\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("b.txt")).unwrap(),
+            "something\n"
+        );
+        assert!(state
+            .text
+            .starts_with("Replaced 'izak' with 'tina', 'nothing' with 'something'"));
+    }
+
+    #[tokio::test]
+    async fn saved_preset_can_be_loaded_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = searching(&dir);
+        state.presets_path = Some(dir.path().join("presets.json"));
+        state.options.whole_word = true;
+
+        run(&mut state, Message::AddRule).await;
+        run(&mut state, Message::RuleFindChanged(0, "E0-0".to_owned())).await;
+        run(
+            &mut state,
+            Message::RuleReplaceChanged(0, "E0-1".to_owned()),
+        )
+        .await;
+        run(&mut state, Message::PresetNameChanged("rename".to_owned())).await;
+        run(&mut state, Message::SavePreset).await;
+        assert_eq!(state.text, "Saved preset 'rename'.");
+
+        let mut state = State {
+            presets: frr::preset::load_presets(&dir.path().join("presets.json")).unwrap(),
+            ..State::default()
+        };
+        run(&mut state, Message::LoadPreset("rename".to_owned())).await;
+
+        assert_eq!(state.find.0, "izak");
+        assert_eq!(state.replace.0, "tina");
+        assert_eq!(
+            state.rules,
+            vec![frr::find_and_replace::Rule::new("E0-0", "E0-1")]
+        );
+        assert_eq!(state.path, dir.path());
+        assert!(state.options.whole_word);
+    }
+
+    #[tokio::test]
+    async fn replace_is_recorded_in_the_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = searching(&dir);
+        state.history_path = Some(dir.path().join("history.jsonl"));
+
+        run(&mut state, Message::Find).await;
+        run(&mut state, Message::Replace).await;
+        run(&mut state, Message::ShowHistory).await;
+
+        assert!(state.show_history);
+        assert_eq!(state.history.len(), 1);
+        assert_eq!(state.history[0].files[0].matches, 2);
+
+        run(&mut state, Message::OpenHistoryEntry(0)).await;
+        assert!(!state.show_history);
+        assert!(state.text.contains("a.txt': 2 matches"));
+    }
+
+    #[tokio::test]
+    async fn rename_mode_renames_names_and_can_be_undone() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = searching(&dir);
+        state.rename = true;
+        fs::create_dir(dir.path().join("izak_run")).unwrap();
+        fs::write(dir.path().join("izak_run/izak.txt"), "izak\n").unwrap();
+
+        run(&mut state, Message::Find).await;
+        assert!(state.text.contains("and 2 names to rename"));
+        assert_eq!(state.renames.len(), 2);
+
+        run(&mut state, Message::Replace).await;
+        let renamed = dir.path().join("tina_run/tina.txt");
+        assert_eq!(fs::read_to_string(&renamed).unwrap(), "tina\n");
+        assert!(!dir.path().join("izak_run").exists());
+
+        run(&mut state, Message::Undo).await;
+        assert_eq!(
+            fs::read_to_string(dir.path().join("izak_run/izak.txt")).unwrap(),
+            "izak\n"
+        );
+        assert!(!dir.path().join("tina_run").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn rename_mode_can_be_undone_through_a_symlinked_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real");
+        fs::create_dir_all(real.join("izak_run")).unwrap();
+        fs::write(real.join("izak_run/a.txt"), "izak\n").unwrap();
+        std::os::unix::fs::symlink(&real, dir.path().join("link")).unwrap();
+        let mut state = State {
+            find: ("izak".to_owned(), "".to_owned()),
+            replace: ("tina".to_owned(), "".to_owned()),
+            path: dir.path().join("link/../link"),
+            backup_dir: dir.path().join(".backups"),
+            rename: true,
+            ..State::default()
+        };
+
+        run(&mut state, Message::Find).await;
+        run(&mut state, Message::Replace).await;
+        assert_eq!(
+            fs::read_to_string(real.join("tina_run/a.txt")).unwrap(),
+            "tina\n"
+        );
+
+        run(&mut state, Message::Undo).await;
+        assert!(state.text.starts_with("Restored"), "{}", state.text);
+        assert_eq!(
+            fs::read_to_string(real.join("izak_run/a.txt")).unwrap(),
+            "izak\n"
+        );
+        assert!(!real.join("tina_run").exists());
+    }
+
+    #[tokio::test]
+    async fn diff_shows_only_selected_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = searching(&dir);
+
+        run(&mut state, Message::Find).await;
+        run(&mut state, Message::ToggleLine(0, 0, false)).await;
+        run(&mut state, Message::ToggleDiff).await;
+        assert!(state.diff.is_some());
+
+        let targets = state.replace_targets();
+        assert_eq!(targets[0].lines, Some(vec![2]));
+
+        run(&mut state, Message::ToggleDiff).await;
+        assert!(state.diff.is_none());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "izak\nizak\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_replace_reports_modified_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = searching(&dir);
+        fs::write(dir.path().join("c.txt"), "izak\n").unwrap();
+        state.workers = 1;
+
+        run(&mut state, Message::Find).await;
+        assert_eq!(state.preview.len(), 2);
+
+        // cancel right after the first file is done, the second is never started
+        let task = update(&mut state, Message::Replace);
+        let mut stream = iced_runtime::task::into_stream(task).unwrap();
+        while let Some(action) = stream.next().await {
+            if let Action::Output(message) = action {
+                let replaced = matches!(message, Message::FileReplaced(..));
+                run(&mut state, message).await;
+                if replaced {
+                    run(&mut state, Message::Cancel).await;
+                }
+            }
+        }
+
+        assert_eq!(state.progress.done, 1);
+        assert!(state.running.is_none());
+        assert!(state.text.contains("a.txt"));
+        assert!(state.text.contains("The replace was cancelled"));
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "tina\ntina\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("c.txt")).unwrap(),
+            "izak\n"
+        );
+
+        run(&mut state, Message::Undo).await;
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "izak\nizak\n"
+        );
+    }
 }
@@ -0,0 +1,264 @@
+use crate::find_and_replace::{FileMatches, FileReport, MatchedLine, Rule};
+use crate::history::HistoryEntry;
+use crate::rename::Rename;
+
+// turns structured search results into text for the iced view and the CLI
+
+// rebuilds the line before and after the replace, passing every matched span
+// and its replacement through `highlight`
+fn render_line(line: &MatchedLine, highlight: fn(&str) -> String) -> (String, String) {
+    let mut old_line = "".to_owned();
+    let mut new_line = "".to_owned();
+    let mut last = 0;
+
+    for m in line.matches.iter() {
+        let start = m.byte_range.start - line.byte_offset;
+        let unchanged = &line.text[last..start];
+
+        old_line = format!("{}{}{}", old_line, unchanged, highlight(&m.before));
+        new_line = format!("{}{}{}", new_line, unchanged, highlight(&m.after));
+        last = m.byte_range.end - line.byte_offset;
+    }
+    old_line.push_str(&line.text[last..]);
+    new_line.push_str(&line.text[last..]);
+
+    (old_line, new_line)
+}
+
+// every line of a multi-line match is highlighted on its own, markdown
+// emphasis can't span line breaks
+fn highlight_pattern(pattern: &str) -> String {
+    pattern
+        .split('\n')
+        .map(|piece| match piece.trim_end_matches('\r') {
+            "" => "".to_owned(),
+            piece => format!("**{}**", piece),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn line_range(line: &MatchedLine) -> String {
+    if line.line == line.end_line {
+        line.line.to_string()
+    } else {
+        format!("{}-{}", line.line, line.end_line)
+    }
+}
+
+// keeps the line breaks of multi-line blocks instead of joining them into one paragraph
+fn hard_breaks(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\n', "  \n")
+}
+
+pub fn markdown_line(line: &MatchedLine) -> String {
+    let (old_line, new_line) = render_line(line, highlight_pattern);
+    format!(
+        "{}: \n\n{}\n\n => {}\n\n",
+        line_range(line),
+        hard_breaks(&old_line),
+        hard_breaks(&new_line)
+    )
+}
+
+// e.g. `'a' with 'b', 'c' with 'd'`
+pub fn describe_rules(rules: &[Rule]) -> String {
+    rules
+        .iter()
+        .map(|rule| format!("'{}' with '{}'", rule.find, rule.replace))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// `old => new`, followed by the reason when the rename can not be done
+pub fn markdown_rename(rename: &Rename) -> String {
+    match &rename.conflict {
+        Some(conflict) => format!(
+            "'{}' => '{}' **(skipped: {})**",
+            rename.from.display(),
+            rename.to.display(),
+            conflict
+        ),
+        None => format!("'{}' => '{}'", rename.from.display(), rename.to.display()),
+    }
+}
+
+pub fn plain_rename(rename: &Rename) -> String {
+    match &rename.conflict {
+        Some(conflict) => format!(
+            "{} => {} (skipped: {})\n",
+            rename.from.display(),
+            rename.to.display(),
+            conflict
+        ),
+        None => format!("{} => {}\n", rename.from.display(), rename.to.display()),
+    }
+}
+
+// one row per file that could not be processed, padded into columns
+pub fn plain_report(reports: &[FileReport]) -> String {
+    let failed: Vec<&FileReport> = reports.iter().filter(|r| r.status.is_error()).collect();
+    let width = failed
+        .iter()
+        .map(|r| r.status.to_string().len())
+        .max()
+        .unwrap_or_default();
+    failed
+        .iter()
+        .map(|r| {
+            format!(
+                "{:width$}  {}\n",
+                r.status.to_string(),
+                r.message
+                    .clone()
+                    .unwrap_or_else(|| r.path.display().to_string()),
+                width = width
+            )
+        })
+        .collect()
+}
+
+// the failed rows of `plain_report` as a markdown code block, empty when nothing failed
+pub fn markdown_report(reports: &[FileReport]) -> String {
+    match plain_report(reports) {
+        failed if failed.is_empty() => failed,
+        failed => format!("```\n{}```\n", failed),
+    }
+}
+
+// `YYYY-MM-DD HH:MM:SS UTC` from seconds since the unix epoch
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // civil date from days since 1970-01-01, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+// one line summary shown in the history list
+pub fn history_summary(entry: &HistoryEntry) -> String {
+    format!(
+        "{}: replaced {} in '{}', {} matches in {} files, {} renames",
+        format_timestamp(entry.timestamp),
+        describe_rules(&entry.rules),
+        entry.dir.display(),
+        entry.matches(),
+        entry.files.len(),
+        entry.renames.len()
+    )
+}
+
+// details of a past replace for the markdown pane
+pub fn markdown_history_entry(entry: &HistoryEntry) -> String {
+    let files: String = entry
+        .files
+        .iter()
+        .map(|file| format!("- '{}': {} matches\n", file.path.display(), file.matches))
+        .chain(
+            entry
+                .renames
+                .iter()
+                .map(|rename| format!("- {}\n", markdown_rename(rename))),
+        )
+        .collect();
+    format!(
+        "### Replace of {}\n\nId: {}\n\nDirectory: '{}'\n\nReplaced {} in the following files:\n\n{}",
+        format_timestamp(entry.timestamp),
+        entry.id,
+        entry.dir.display(),
+        describe_rules(&entry.rules),
+        files
+    )
+}
+
+// grep like `path:line: old => new` output for the terminal
+pub fn plain_file(file: &FileMatches) -> String {
+    let path = file.path.display().to_string();
+    file.lines
+        .iter()
+        .map(|line| {
+            let (old_line, new_line) = render_line(line, str::to_owned);
+            let range = line_range(line);
+            format!(
+                "{}:{}: {}\n{}=> {}\n",
+                path,
+                range,
+                old_line,
+                " ".repeat(path.chars().count() + range.len()),
+                new_line
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_timestamp, markdown_line, plain_file};
+    use crate::find_and_replace::{FileMatches, Match, MatchedLine};
+    use std::path::PathBuf;
+
+    fn sample() -> FileMatches {
+        let m = Match {
+            path: PathBuf::from("a.txt"),
+            rule: 0,
+            line: 2,
+            end_line: 2,
+            column: 3,
+            byte_range: 8..12,
+            before: "izak".to_owned(),
+            after: "tina".to_owned(),
+        };
+        FileMatches {
+            path: PathBuf::from("a.txt"),
+            lines: vec![MatchedLine {
+                line: 2,
+                end_line: 2,
+                byte_offset: 6,
+                text: "a izak b".to_owned(),
+                matches: vec![m],
+            }],
+            hash: 0,
+        }
+    }
+
+    #[test]
+    fn markdown_line_highlights_both_sides() {
+        assert_eq!(
+            markdown_line(&sample().lines[0]),
+            "2: \n\na **izak** b\n\n => a **tina** b\n\n"
+        );
+    }
+
+    #[test]
+    fn plain_file_shows_old_and_new_line() {
+        assert_eq!(
+            plain_file(&sample()),
+            "a.txt:2: a izak b\n      => a tina b\n"
+        );
+    }
+
+    #[test]
+    fn format_timestamp_gives_utc_date() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(1709210096), "2024-02-29 12:34:56 UTC");
+    }
+}
@@ -0,0 +1,137 @@
+use anyhow::{Context, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::dir_crawl::{CrawlOptions, DEFAULT_FILTER};
+use crate::find_and_replace::{write_atomic, Rule, SearchOptions};
+
+// a named set of rules, directory, file filter and match options that can be
+// saved and loaded again for recurring migrations
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+    pub rules: Vec<Rule>,
+    #[serde(default)]
+    pub dir: String,
+    #[serde(default = "default_filter")]
+    pub filter: String,
+    #[serde(default)]
+    pub options: SearchOptions,
+    #[serde(default)]
+    pub crawl: CrawlOptions,
+}
+
+fn default_filter() -> String {
+    DEFAULT_FILTER.to_owned()
+}
+
+// layout of the config file, kept as an object so other settings can be added
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Config {
+    #[serde(default)]
+    presets: Vec<Preset>,
+}
+
+// `~/.config/frr`, also holding the history journal
+pub fn config_dir() -> Result<PathBuf, Error> {
+    let home = home::home_dir().context("Could not find the home directory")?;
+    Ok(home.join(".config").join("frr"))
+}
+
+pub fn config_path() -> Result<PathBuf, Error> {
+    Ok(config_dir()?.join("presets.json"))
+}
+
+// a missing config file simply means no presets were saved yet
+pub fn load_presets(path: &Path) -> Result<Vec<Preset>, Error> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read presets from {:?}", path))?;
+    let config: Config = serde_json::from_str(&content)
+        .with_context(|| format!("Invalid presets file {:?}", path))?;
+    Ok(config.presets)
+}
+
+pub async fn save_presets(path: PathBuf, presets: Vec<Preset>) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Could not create config directory {:?}", dir))?;
+    }
+    // write_atomic resolves the path, so the file has to exist beforehand
+    if !path.exists() {
+        tokio::fs::write(&path, "").await?;
+    }
+    let content = serde_json::to_string_pretty(&Config { presets })?;
+    write_atomic(&path, content.as_bytes())
+        .await
+        .with_context(|| format!("Could not save presets to {:?}", path))
+}
+
+// replaces the preset with the same name or appends a new one
+pub fn upsert_preset(presets: &mut Vec<Preset>, preset: Preset) {
+    match presets.iter_mut().find(|p| p.name == preset.name) {
+        Some(existing) => *existing = preset,
+        None => presets.push(preset),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_presets, save_presets, upsert_preset, Preset};
+    use crate::dir_crawl::CrawlOptions;
+    use crate::find_and_replace::{Rule, SearchOptions};
+    use std::fs;
+
+    fn preset(name: &str, find: &str) -> Preset {
+        Preset {
+            name: name.to_owned(),
+            rules: vec![Rule::new(find, "tina")],
+            dir: "/data".to_owned(),
+            filter: "*.params".to_owned(),
+            options: SearchOptions {
+                whole_word: true,
+                ..SearchOptions::default()
+            },
+            crawl: CrawlOptions {
+                exclude_dirs: vec!["target".to_owned()],
+                ..CrawlOptions::default()
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn presets_survive_a_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("frr").join("presets.json");
+        assert!(load_presets(&path).unwrap().is_empty());
+
+        let mut presets = vec![preset("rename", "izak")];
+        upsert_preset(&mut presets, preset("other", "E0-0"));
+        upsert_preset(&mut presets, preset("rename", "Izak"));
+        save_presets(path.clone(), presets.clone()).await.unwrap();
+
+        let loaded = load_presets(&path).unwrap();
+        assert_eq!(loaded, presets);
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].rules[0].find, "Izak");
+    }
+
+    #[test]
+    fn load_presets_fills_in_missing_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("presets.json");
+        fs::write(
+            &path,
+            r#"{"presets": [{"name": "a", "rules": [{"find": "x", "replace": "y"}]}]}"#,
+        )
+        .unwrap();
+
+        let loaded = load_presets(&path).unwrap();
+        assert_eq!(loaded[0].filter, "*.txt");
+        assert_eq!(loaded[0].options, SearchOptions::default());
+        assert_eq!(loaded[0].crawl, CrawlOptions::default());
+    }
+}
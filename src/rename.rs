@@ -0,0 +1,268 @@
+use anyhow::{bail, Context, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::backup::Backup;
+use crate::dir_crawl::{error_depth, walker, CrawlOptions};
+use crate::find_and_replace::{
+    compile_rules, replace_in_text, serialize_path, FileReport, Rule, SearchOptions,
+};
+
+// a file or directory whose name matches one of the rules
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rename {
+    #[serde(serialize_with = "serialize_path")]
+    pub from: PathBuf,
+    #[serde(serialize_with = "serialize_path")]
+    pub to: PathBuf,
+    // why the rename can not be done, e.g. the new name already exists
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conflict: Option<String>,
+}
+
+// collects every entry below `root` together with its depth, symlinks are
+// renamed themselves but never followed, entries below the root that can not
+// be read are only counted
+fn collect_entries(root: &Path, crawl: &CrawlOptions) -> Result<(Vec<(usize, PathBuf)>, usize)> {
+    let mut entries = vec![];
+    let mut skipped = 0;
+    for entry in walker(root, crawl).build() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) if error_depth(&e).is_some_and(|depth| depth > 0) => {
+                skipped += 1;
+                continue;
+            }
+            Err(e) => return Err(e).with_context(|| format!("Could not walk {:?}", root)),
+        };
+        // the root itself keeps its name
+        if entry.depth() > 0 {
+            entries.push((entry.depth(), entry.into_path()));
+        }
+    }
+    Ok((entries, skipped))
+}
+
+// every file and directory name under `root` the rules would change, deepest
+// first so renaming a directory never moves a path that is still to be renamed,
+// together with the number of entries that had to be skipped
+pub fn find_renames(
+    root: &Path,
+    rules: &[Rule],
+    options: &SearchOptions,
+    crawl: &CrawlOptions,
+) -> Result<(Vec<Rename>, usize), Error> {
+    let rules = compile_rules(rules, options)?;
+    let (mut entries, skipped) = collect_entries(root, crawl)?;
+    entries.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+    let mut renames = vec![];
+    for (_, path) in entries {
+        // names that are not valid UTF-8 can not be matched
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let Some(new_name) = replace_in_text(&rules, name, options) else {
+            continue;
+        };
+        if new_name == name {
+            continue;
+        }
+        let conflict = if new_name.is_empty()
+            || new_name == "."
+            || new_name == ".."
+            || new_name.contains(['/', '\0'])
+        {
+            Some(format!("'{}' is not a valid name", new_name))
+        } else {
+            None
+        };
+        renames.push(Rename {
+            to: path.with_file_name(&new_name),
+            from: path,
+            conflict,
+        });
+    }
+
+    mark_collisions(&mut renames);
+    Ok((renames, skipped))
+}
+
+// a rename collides when its new path already exists or another rename ends
+// up at the same path
+fn mark_collisions(renames: &mut [Rename]) {
+    let mut targets: HashMap<PathBuf, usize> = HashMap::new();
+    for rename in renames.iter() {
+        *targets.entry(rename.to.clone()).or_default() += 1;
+    }
+
+    for rename in renames
+        .iter_mut()
+        .filter(|rename| rename.conflict.is_none())
+    {
+        if targets[&rename.to] > 1 {
+            rename.conflict = Some(format!(
+                "Several names would be renamed to '{}'",
+                rename.to.display()
+            ));
+        } else if fs::symlink_metadata(&rename.to).is_ok() {
+            rename.conflict = Some(format!("'{}' already exists", rename.to.display()));
+        }
+    }
+}
+
+pub fn check_conflicts(renames: &[Rename]) -> Result<(), Error> {
+    let conflicts: Vec<String> = renames
+        .iter()
+        .filter_map(|rename| rename.conflict.clone())
+        .collect();
+    if !conflicts.is_empty() {
+        bail!(
+            "Refusing to rename, the following names collide:\n\n- {}",
+            conflicts.join("\n- ")
+        );
+    }
+    Ok(())
+}
+
+// renames in the given order, refusing to start if any rename has a conflict,
+// a rename that fails later on is reported and the others still go ahead
+pub async fn rename_all(
+    renames: Vec<Rename>,
+    backup: &mut Backup,
+) -> Result<(String, Vec<FileReport>), Error> {
+    check_conflicts(&renames)?;
+
+    let mut output = "".to_owned();
+    let mut reports = vec![];
+    for rename in renames.into_iter() {
+        // the tree may have changed since the preview
+        let result = if tokio::fs::symlink_metadata(&rename.to).await.is_ok() {
+            Err(Error::msg(format!(
+                "'{}' already exists",
+                rename.to.display()
+            )))
+        } else {
+            tokio::fs::rename(&rename.from, &rename.to)
+                .await
+                .with_context(|| format!("Could not rename '{}'", rename.from.display()))
+        };
+        reports.push(FileReport::renamed(rename.from.clone(), &result));
+        if result.is_ok() {
+            output = format!(
+                "{}\n- '{}' => '{}'\n",
+                output,
+                rename.from.display(),
+                rename.to.display()
+            );
+            backup.renames.push(rename);
+        }
+    }
+    Ok((output, reports))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_renames, rename_all};
+    use crate::backup::Backup;
+    use crate::dir_crawl::CrawlOptions;
+    use crate::find_and_replace::{FileStatus, Rule, SearchOptions};
+    use std::fs;
+
+    #[tokio::test]
+    async fn renames_deepest_first_and_can_be_undone() {
+        let dir = tempfile::tempdir().unwrap();
+        let run = dir.path().join("E0-0_01");
+        fs::create_dir_all(&run).unwrap();
+        fs::write(run.join("E0-0_01.c.mzXML"), "E0-0_01").unwrap();
+        fs::write(run.join("other.txt"), "").unwrap();
+
+        let rules = vec![Rule::new("E0-0", "E0-1")];
+        let (renames, skipped) = find_renames(
+            dir.path(),
+            &rules,
+            &SearchOptions::default(),
+            &CrawlOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(skipped, 0);
+        assert_eq!(renames.len(), 2);
+        assert!(renames[0].from.ends_with("E0-0_01/E0-0_01.c.mzXML"));
+        assert!(renames[1].to.ends_with("E0-1_01"));
+
+        let mut backup = Backup::in_dir(dir.path());
+        rename_all(renames, &mut backup).await.unwrap();
+        let renamed = dir.path().join("E0-1_01");
+        assert!(renamed.join("E0-1_01.c.mzXML").exists());
+        assert!(renamed.join("other.txt").exists());
+        assert!(!run.exists());
+
+        backup.restore().await.unwrap();
+        assert!(run.join("E0-0_01.c.mzXML").exists());
+        assert!(!renamed.exists());
+    }
+
+    #[tokio::test]
+    async fn colliding_renames_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "").unwrap();
+        fs::write(dir.path().join("b.txt"), "").unwrap();
+        fs::write(dir.path().join("c.txt"), "").unwrap();
+
+        let rules = vec![Rule::new("a", "b"), Rule::new("c", "d")];
+        let (renames, _) = find_renames(
+            dir.path(),
+            &rules,
+            &SearchOptions::default(),
+            &CrawlOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(renames.len(), 2);
+        assert!(renames[0]
+            .conflict
+            .as_ref()
+            .unwrap()
+            .contains("already exists"));
+        assert!(renames[1].conflict.is_none());
+
+        assert!(rename_all(renames, &mut Backup::in_dir(dir.path()))
+            .await
+            .is_err());
+        assert!(dir.path().join("a.txt").exists());
+        assert!(dir.path().join("c.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn failed_renames_are_reported_and_the_rest_go_ahead() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "").unwrap();
+        fs::write(dir.path().join("c.txt"), "").unwrap();
+
+        let rules = vec![Rule::new("a", "b"), Rule::new("c", "d")];
+        let (renames, _) = find_renames(
+            dir.path(),
+            &rules,
+            &SearchOptions::default(),
+            &CrawlOptions::default(),
+        )
+        .unwrap();
+        // created after the preview, so only the rename itself notices
+        fs::write(dir.path().join("b.txt"), "").unwrap();
+
+        let mut backup = Backup::in_dir(dir.path());
+        let (_, reports) = rename_all(renames, &mut backup).await.unwrap();
+
+        let statuses: Vec<FileStatus> = reports.iter().map(|report| report.status).collect();
+        assert_eq!(statuses, vec![FileStatus::NotRenamed, FileStatus::Renamed]);
+        assert!(reports[0]
+            .message
+            .as_ref()
+            .unwrap()
+            .contains("already exists"));
+        assert!(dir.path().join("a.txt").exists());
+        assert!(dir.path().join("d.txt").exists());
+        assert_eq!(backup.renames.len(), 1);
+    }
+}
@@ -1 +1,281 @@
+use frr::backup::Backup;
+use frr::dir_crawl::{dir_crawl, CrawlOptions, FileFilter};
+use frr::find_and_replace::{
+    find_from_vec, replace_from_vec, FileReport, FileStatus, ReplaceTarget, Rule, SearchOptions,
+    DEFAULT_WORKERS,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
 
+// builds a small search folder like the ones in data/
+fn sample_tree() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let files = [
+        ("hudnik/test1.txt", "aaaaaizakaaaa   and izak\n"),
+        ("test2/test2.txt", "izak   or izak\n"),
+        ("zajec/test3.txt", "nothing to see\n"),
+        ("zajec/notes.md", "izak\n"),
+    ];
+    for (name, contents) in files {
+        let path = dir.path().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    dir
+}
+
+fn crawl(dir: &Path) -> Vec<PathBuf> {
+    let (mut files, _) = dir_crawl(dir, &FileFilter::default(), &CrawlOptions::default()).unwrap();
+    files.sort();
+    files
+}
+
+#[test]
+fn dir_crawl_collects_filtered_files() {
+    let dir = sample_tree();
+
+    let files = crawl(dir.path());
+
+    assert_eq!(files.len(), 3);
+    assert!(files
+        .iter()
+        .all(|file| file.extension().is_some_and(|ext| ext == "txt")));
+}
+
+#[tokio::test]
+async fn find_from_vec_lists_only_matching_files() {
+    let dir = sample_tree();
+
+    let (files, reports) = find_from_vec(
+        vec![Rule::new("izak", "tina")],
+        crawl(dir.path()),
+        SearchOptions::default(),
+        DEFAULT_WORKERS,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(files.len(), 2);
+    assert_eq!(reports.len(), 3);
+    assert_eq!(
+        files
+            .iter()
+            .map(|file| file.matches().count())
+            .sum::<usize>(),
+        4
+    );
+}
+
+#[tokio::test]
+async fn find_from_vec_reports_nothing_found() {
+    let dir = sample_tree();
+
+    let result = find_from_vec(
+        vec![Rule::new("tina", "izak")],
+        crawl(dir.path()),
+        SearchOptions::default(),
+        DEFAULT_WORKERS,
+    )
+    .await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn find_from_vec_reports_files_it_could_not_read() {
+    let dir = sample_tree();
+    let binary = dir.path().join("zajec/binary.txt");
+    fs::write(&binary, [0x69, 0xff, 0xfe, 0x7a]).unwrap();
+
+    let (files, reports) = find_from_vec(
+        vec![Rule::new("izak", "tina")],
+        crawl(dir.path()),
+        SearchOptions::default(),
+        DEFAULT_WORKERS,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(files.len(), 2);
+    let failed: Vec<&FileReport> = reports
+        .iter()
+        .filter(|report| report.status.is_error())
+        .collect();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].status, FileStatus::NotUtf8);
+    assert!(failed[0].message.as_ref().unwrap().contains("binary.txt"));
+}
+
+#[tokio::test]
+async fn replace_from_vec_replaces_and_can_be_undone() {
+    let dir = sample_tree();
+    let targets = crawl(dir.path())
+        .into_iter()
+        .map(ReplaceTarget::from)
+        .collect();
+
+    let (text, backup, reports) = replace_from_vec(
+        vec![Rule::new("izak", "tina")],
+        targets,
+        SearchOptions::default(),
+        DEFAULT_WORKERS,
+        Backup::in_dir(dir.path()),
+    )
+    .await
+    .unwrap();
+    let replaced: Vec<_> = reports
+        .iter()
+        .filter_map(FileReport::replaced_file)
+        .collect();
+
+    assert_eq!(backup.files.len(), 2);
+    assert_eq!(replaced.len(), 2);
+    assert!(replaced.iter().all(|file| file.matches == 2));
+    assert!(text.contains("test1.txt") && text.contains("test2.txt"));
+    assert!(!text.contains("test3.txt"));
+    let test1 = dir.path().join("hudnik/test1.txt");
+    assert_eq!(
+        fs::read_to_string(&test1).unwrap(),
+        "aaaaatinaaaaa   and tina\n"
+    );
+    // files outside the filter are never touched
+    assert_eq!(
+        fs::read_to_string(dir.path().join("zajec/notes.md")).unwrap(),
+        "izak\n"
+    );
+
+    backup.restore().await.unwrap();
+    assert_eq!(
+        fs::read_to_string(&test1).unwrap(),
+        "aaaaaizakaaaa   and izak\n"
+    );
+}
+
+#[tokio::test]
+async fn replace_from_vec_rejects_invalid_regex() {
+    let dir = sample_tree();
+    let targets = crawl(dir.path())
+        .into_iter()
+        .map(ReplaceTarget::from)
+        .collect();
+
+    let result = replace_from_vec(
+        vec![Rule::new("(", "tina")],
+        targets,
+        SearchOptions {
+            regex: true,
+            ..SearchOptions::default()
+        },
+        DEFAULT_WORKERS,
+        Backup::in_dir(dir.path()),
+    )
+    .await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn replace_from_vec_keeps_order_with_several_workers() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths: Vec<PathBuf> = (0..20)
+        .map(|n| {
+            let path = dir.path().join(format!("{:02}.txt", n));
+            fs::write(&path, "izak\n".repeat(n + 1)).unwrap();
+            path
+        })
+        .collect();
+
+    let (files, _) = find_from_vec(
+        vec![Rule::new("izak", "tina")],
+        paths.clone(),
+        SearchOptions::default(),
+        3,
+    )
+    .await
+    .unwrap();
+    let found: Vec<PathBuf> = files.into_iter().map(|file| file.path).collect();
+    assert_eq!(found, paths);
+
+    let (_, backup, reports) = replace_from_vec(
+        vec![Rule::new("izak", "tina")],
+        paths.iter().cloned().map(ReplaceTarget::from).collect(),
+        SearchOptions::default(),
+        3,
+        Backup::in_dir(dir.path()),
+    )
+    .await
+    .unwrap();
+    let counts: Vec<usize> = reports.iter().map(|file| file.matches).collect();
+    assert_eq!(counts, (1..=20).collect::<Vec<usize>>());
+
+    backup.restore().await.unwrap();
+    assert_eq!(fs::read_to_string(&paths[19]).unwrap(), "izak\n".repeat(20));
+}
+
+#[tokio::test]
+async fn replace_from_vec_reports_files_it_could_not_replace() {
+    let dir = tempfile::tempdir().unwrap();
+    let replaced = dir.path().join("a.txt");
+    let untouched = dir.path().join("b.txt");
+    let binary = dir.path().join("c.txt");
+    let missing = dir.path().join("d.txt");
+    fs::write(&replaced, "izak\n").unwrap();
+    fs::write(&untouched, "tina\n").unwrap();
+    fs::write(&binary, [0x69, 0xff, 0xfe, 0x7a]).unwrap();
+    let paths = [&replaced, &untouched, &binary, &missing];
+
+    let (_, _, reports) = replace_from_vec(
+        vec![Rule::new("izak", "tina")],
+        paths
+            .iter()
+            .map(|path| ReplaceTarget::from(path.to_path_buf()))
+            .collect(),
+        SearchOptions::default(),
+        DEFAULT_WORKERS,
+        Backup::in_dir(dir.path()),
+    )
+    .await
+    .unwrap();
+
+    let statuses: Vec<FileStatus> = reports.iter().map(|report| report.status).collect();
+    assert_eq!(
+        statuses,
+        vec![
+            FileStatus::Replaced,
+            FileStatus::NoMatch,
+            FileStatus::NotUtf8,
+            FileStatus::Unreadable
+        ]
+    );
+    assert!(reports[3].message.as_ref().unwrap().contains("d.txt"));
+    assert_eq!(fs::read_to_string(&replaced).unwrap(), "tina\n");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn non_utf8_file_names_are_found_and_replaced() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(OsStr::from_bytes(b"izak\xff.txt"));
+    fs::write(&path, "izak\n").unwrap();
+
+    let files = crawl(dir.path());
+    assert_eq!(files, vec![path.clone()]);
+
+    let (_, _, reports) = replace_from_vec(
+        vec![Rule::new("izak", "tina")],
+        files.into_iter().map(ReplaceTarget::from).collect(),
+        SearchOptions::default(),
+        DEFAULT_WORKERS,
+        Backup::in_dir(dir.path()),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(reports[0].status, FileStatus::Replaced);
+    assert_eq!(reports[0].path, path);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "tina\n");
+}